@@ -0,0 +1,539 @@
+//! Rewriting of the ANSI escape sequences in recolor's output on their way to
+//! the terminal.
+
+use clap::ValueEnum;
+use std::io::{self, Write};
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+pub type Rgb = (u8, u8, u8);
+
+/// The 16 standard colors, as xterm shows them.
+pub const XTERM_COLORS: [Rgb; 16] = [
+    (0x00, 0x00, 0x00),
+    (0xcd, 0x00, 0x00),
+    (0x00, 0xcd, 0x00),
+    (0xcd, 0xcd, 0x00),
+    (0x00, 0x00, 0xee),
+    (0xcd, 0x00, 0xcd),
+    (0x00, 0xcd, 0xcd),
+    (0xe5, 0xe5, 0xe5),
+    (0x7f, 0x7f, 0x7f),
+    (0xff, 0x00, 0x00),
+    (0x00, 0xff, 0x00),
+    (0xff, 0xff, 0x00),
+    (0x5c, 0x5c, 0xff),
+    (0xff, 0x00, 0xff),
+    (0x00, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+
+/// Returns the color for an index into the xterm 256 color palette.
+pub fn xterm_color(i: u8) -> Rgb {
+    match i {
+        0..=15 => XTERM_COLORS[i as usize],
+        16..=231 => {
+            let i = i - 16;
+            let level = |n: u8| if n == 0 { 0 } else { 55 + n * 40 };
+            (level(i / 36), level(i / 6 % 6), level(i % 6))
+        }
+        _ => {
+            let grey = 8 + (i - 232) * 10;
+            (grey, grey, grey)
+        }
+    }
+}
+
+/// Returns the index of the color in `colors` that's closest to `color`.
+fn nearest(color: Rgb, colors: impl Iterator<Item = Rgb>) -> usize {
+    let distance = |(r, g, b): Rgb| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, color.0) + d(g, color.1) + d(b, color.2)
+    };
+    colors
+        .enumerate()
+        .min_by_key(|(_, c)| distance(*c))
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+/// How many colors the terminal can show.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorDepth {
+    /// Just the 16 standard colors.
+    #[value(name = "16")]
+    Ansi16,
+    /// The xterm 256 color palette.
+    #[value(name = "256")]
+    Ansi256,
+    /// Any 24-bit color.
+    #[default]
+    #[value(name = "truecolor")]
+    TrueColor,
+}
+
+impl ColorDepth {
+    /// Guesses how many colors the terminal can show from the `COLORTERM` and
+    /// `TERM` environment variables. Without `TERM` there's nothing to go on,
+    /// so colors are left alone.
+    pub fn detect(colorterm: Option<&str>, term: Option<&str>) -> ColorDepth {
+        match (colorterm, term) {
+            (Some("truecolor" | "24bit"), _) | (_, None) => ColorDepth::TrueColor,
+            (_, Some(term)) if term.contains("256color") => ColorDepth::Ansi256,
+            (_, Some(_)) => ColorDepth::Ansi16,
+        }
+    }
+}
+
+/// What to do with OSC sequences, like the ones that set the terminal's title.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum OscMode {
+    /// Write them out as they are.
+    #[default]
+    Pass,
+    /// Leave them out.
+    Strip,
+    /// Write them out, and print what's in them to stderr.
+    Report,
+}
+
+/// How escape sequences should be rewritten.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FilterOptions {
+    /// Wrap every escape sequence in tmux's DCS passthrough sequence, so that
+    /// it reaches the outer terminal when running inside nested sessions.
+    pub tmux_passthrough: bool,
+    /// Replace colors the terminal can't show with the closest ones it can.
+    pub depth: ColorDepth,
+    /// The width of the terminal, to turn styles back on at the start of each
+    /// row that it wraps a long line onto.
+    pub wrap_width: Option<usize>,
+    pub osc: OscMode,
+    /// Hold SGR sequences back until there's text to write, then write one
+    /// sequence with just the attributes that changed.
+    pub compact: bool,
+}
+
+impl FilterOptions {
+    fn is_noop(&self) -> bool {
+        !self.tmux_passthrough
+            && self.depth == ColorDepth::TrueColor
+            && self.wrap_width.is_none()
+            && self.osc == OscMode::Pass
+            && !self.compact
+    }
+}
+
+/// The SGR attributes in effect, for `compact`.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Sgr {
+    /// The parameters that turned on attributes like bold, in the order
+    /// they were turned on.
+    attributes: Vec<u16>,
+    /// The parameters that set the foreground and background colors, like
+    /// `31` or `38;5;208`.
+    fg: Option<String>,
+    bg: Option<String>,
+}
+
+/// Returns the SGR parameter that turns off an attribute, or `None` if only
+/// a reset does.
+fn attribute_off(param: u16) -> Option<u16> {
+    match param {
+        1 | 2 => Some(22),
+        3 => Some(23),
+        4 => Some(24),
+        5 | 6 => Some(25),
+        7 => Some(27),
+        8 => Some(28),
+        9 => Some(29),
+        51 | 52 => Some(54),
+        53 => Some(55),
+        _ => None,
+    }
+}
+
+impl Sgr {
+    /// Applies the parameters of an SGR sequence, `ESC [ ... m`.
+    fn apply(&mut self, params: &str) {
+        let params: Vec<&str> = params.split(';').collect();
+        let mut i = 0;
+        while i < params.len() {
+            let param: u16 = params[i].parse().unwrap_or(0);
+            // Extended colors take their parameters with them.
+            let len = match (param, params.get(i + 1)) {
+                (38 | 48, Some(&"5")) => 3,
+                (38 | 48, Some(&"2")) => 5,
+                _ => 1,
+            };
+            let color = || Some(params[i..(i + len).min(params.len())].join(";"));
+            match param {
+                0 => *self = Sgr::default(),
+                30..=38 | 90..=97 => self.fg = color(),
+                40..=48 | 100..=107 => self.bg = color(),
+                39 => self.fg = None,
+                49 => self.bg = None,
+                // These turn off the attributes they're the opposite of.
+                22..=29 | 54 | 55 => self.attributes.retain(|&a| attribute_off(a) != Some(param)),
+                _ if !self.attributes.contains(&param) => self.attributes.push(param),
+                _ => {}
+            }
+            i += len;
+        }
+    }
+
+    /// Returns the parameters of an SGR sequence that sets every attribute,
+    /// after a reset.
+    fn params(&self) -> Vec<String> {
+        let mut params: Vec<String> = self.attributes.iter().map(u16::to_string).collect();
+        params.extend(self.fg.clone());
+        params.extend(self.bg.clone());
+        params
+    }
+
+    /// Returns the parameters of the shortest SGR sequence that changes the
+    /// attributes from these to `to`.
+    fn diff(&self, to: &Sgr) -> String {
+        let mut reset = vec!["0".to_string()];
+        reset.extend(to.params());
+        let reset = reset.join(";");
+
+        let mut offs: Vec<u16> = Vec::new();
+        for &param in self
+            .attributes
+            .iter()
+            .filter(|a| !to.attributes.contains(a))
+        {
+            // Some attributes can only be turned off with a reset.
+            let Some(off) = attribute_off(param) else {
+                return reset;
+            };
+            if !offs.contains(&off) {
+                offs.push(off);
+            }
+        }
+        let mut params: Vec<String> = offs.iter().map(u16::to_string).collect();
+        // Turning one attribute off can turn off others that share its
+        // parameter, like bold and dim, which then need turning back on.
+        params.extend(
+            to.attributes
+                .iter()
+                .filter(|a| {
+                    !self.attributes.contains(a)
+                        || attribute_off(**a).is_some_and(|off| offs.contains(&off))
+                })
+                .map(u16::to_string),
+        );
+        for (from, to, off) in [(&self.fg, &to.fg, "39"), (&self.bg, &to.bg, "49")] {
+            if from != to {
+                params.push(to.clone().unwrap_or(off.to_string()));
+            }
+        }
+        let params = params.join(";");
+        if *to == Sgr::default() || params.len() > reset.len() {
+            reset
+        } else {
+            params
+        }
+    }
+}
+
+/// Rewrites the parameters of an SGR sequence so that any 256 or 24-bit
+/// colors become the closest color available at `depth`.
+fn downsample(params: &str, depth: ColorDepth) -> String {
+    let params: Vec<&str> = params.split(';').collect();
+    let mut rewritten: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < params.len() {
+        let number = |i: usize| params.get(i).and_then(|p| p.parse::<u8>().ok());
+        let color = match (params[i], number(i + 1)) {
+            ("38" | "48", Some(5)) => number(i + 2).map(|n| (xterm_color(n), 3)),
+            ("38" | "48", Some(2)) => match (number(i + 2), number(i + 3), number(i + 4)) {
+                (Some(r), Some(g), Some(b)) => Some(((r, g, b), 5)),
+                _ => None,
+            },
+            _ => None,
+        };
+        let Some((color, len)) = color else {
+            rewritten.push(params[i].to_string());
+            i += 1;
+            continue;
+        };
+
+        let background = params[i] == "48";
+        match depth {
+            ColorDepth::Ansi16 => {
+                let n = nearest(color, XTERM_COLORS.into_iter()) as u8;
+                let code = match (n < 8, background) {
+                    (true, false) => 30 + n,
+                    (false, false) => 90 + n - 8,
+                    (true, true) => 40 + n,
+                    (false, true) => 100 + n - 8,
+                };
+                rewritten.push(code.to_string());
+            }
+            // Colors that are already in the 256 color palette can stay as
+            // they are.
+            ColorDepth::Ansi256 if len == 5 => {
+                // The first 16 colors vary between terminals, so only pick
+                // from the fixed color cube and greys.
+                let n = nearest(color, (16..=255).map(xterm_color)) + 16;
+                rewritten.push(format!("{};5;{}", params[i], n));
+            }
+            ColorDepth::Ansi256 | ColorDepth::TrueColor => {
+                rewritten.extend(params[i..i + len].iter().map(|p| p.to_string()))
+            }
+        }
+        i += len;
+    }
+    rewritten.join(";")
+}
+
+/// A writer that finds escape sequences in what's written to it and rewrites
+/// them according to its options. Sequences can be split across writes, so
+/// `flush` must be called once everything has been written.
+pub struct Filter<W: Write> {
+    inner: W,
+    options: FilterOptions,
+    /// The escape sequence currently being read, if any.
+    sequence: Vec<u8>,
+    /// How many characters have been written since the last line or row
+    /// started, with `wrap_width`.
+    column: usize,
+    /// The SGR sequences written since styles were last reset, with
+    /// `wrap_width`.
+    active: Vec<u8>,
+    /// The attributes the terminal has, and the ones the sequences held back
+    /// so far ask for, with `compact`. What the terminal has isn't known
+    /// until something's been written.
+    written: Option<Sgr>,
+    wanted: Sgr,
+}
+
+impl<W: Write> Filter<W> {
+    pub fn new(inner: W, options: FilterOptions) -> Self {
+        Filter {
+            inner,
+            options,
+            sequence: Vec::new(),
+            column: 0,
+            active: Vec::new(),
+            written: None,
+            wanted: Sgr::default(),
+        }
+    }
+
+    /// Writes the SGR sequence that the held back sequences add up to, if
+    /// they changed anything, with `compact`.
+    fn sync(&mut self, out: &mut Vec<u8>) {
+        let written = self.written.take().unwrap_or_default();
+        if self.wanted != written {
+            let sequence = format!("\x1b[{}m", written.diff(&self.wanted));
+            self.wrap(sequence.as_bytes(), out);
+        }
+        self.written = Some(self.wanted.clone());
+    }
+
+    /// Writes a byte of text, first turning the active styles back on if
+    /// it starts a character that the terminal will wrap onto a new row.
+    fn text(&mut self, b: u8, out: &mut Vec<u8>) {
+        let Some(width) = self.options.wrap_width else {
+            out.push(b);
+            return;
+        };
+        match b {
+            b'\n' | b'\r' => self.column = 0,
+            b'\t' => self.column = ((self.column / 8 + 1) * 8).min(width.saturating_sub(1)),
+            // Other control characters take up no space, and the rest of a
+            // UTF-8 character goes with its first byte.
+            0x00..=0x1f | 0x7f..=0xbf => {}
+            _ => {
+                if self.column >= width {
+                    out.extend_from_slice(&self.active);
+                    self.column = 0;
+                }
+                self.column += 1;
+            }
+        }
+        out.push(b);
+    }
+
+    /// Keeps track of which styles are on, given an escape sequence and what
+    /// it was rewritten to.
+    fn track(&mut self, sequence: &[u8], rewritten: &[u8]) {
+        if let [ESC, b'[', params @ .., b'm'] = sequence {
+            if params.is_empty() || params == b"0" {
+                self.active.clear();
+            } else {
+                self.active.extend_from_slice(rewritten);
+            }
+        }
+    }
+
+    fn rewrite(&mut self, sequence: &[u8], out: &mut Vec<u8>) {
+        if let [ESC, b']', body @ ..] = sequence {
+            match self.options.osc {
+                OscMode::Pass => {}
+                OscMode::Strip => return,
+                OscMode::Report => {
+                    let body = body
+                        .strip_suffix(b"\x1b\\")
+                        .or_else(|| body.strip_suffix(&[BEL]))
+                        .unwrap_or(body);
+                    eprintln!("osc: {}", String::from_utf8_lossy(body).escape_debug());
+                }
+            }
+        }
+
+        let downsampled;
+        let sequence = match sequence {
+            [ESC, b'[', params @ .., b'm'] if self.options.depth != ColorDepth::TrueColor => {
+                let params = String::from_utf8_lossy(params);
+                downsampled = format!("\x1b[{}m", downsample(&params, self.options.depth));
+                downsampled.as_bytes()
+            }
+            _ => sequence,
+        };
+
+        if let [ESC, b'[', params @ .., b'm'] = sequence {
+            if self.options.compact {
+                // Before anything's been written, the terminal could have any
+                // attributes, so a reset is written rather than held back.
+                if self.written.is_none() && matches!(params, b"" | b"0") {
+                    self.written = Some(Sgr::default());
+                    self.wanted = Sgr::default();
+                } else {
+                    self.wanted.apply(&String::from_utf8_lossy(params));
+                    return;
+                }
+            }
+        }
+        self.wrap(sequence, out);
+    }
+
+    /// Writes an escape sequence, wrapped for tmux if that's been asked for.
+    fn wrap(&self, sequence: &[u8], out: &mut Vec<u8>) {
+        if self.options.tmux_passthrough {
+            out.extend_from_slice(b"\x1bPtmux;");
+            for &b in sequence {
+                if b == ESC {
+                    out.push(ESC);
+                }
+                out.push(b);
+            }
+            out.extend_from_slice(b"\x1b\\");
+        } else {
+            out.extend_from_slice(sequence);
+        }
+    }
+}
+
+/// Cuts `line` short after `max` characters of text, not counting escape
+/// sequences, or returns `None` if it isn't that long. The sequences past the
+/// cut are kept, so that any styles they reset don't carry on after it.
+pub fn truncate(line: &str, max: usize) -> Option<String> {
+    let (text, escapes) = split_escapes(line);
+    let (end, _) = text.char_indices().nth(max)?;
+    let mut truncated = String::with_capacity(line.len());
+    let mut last = 0;
+    for (position, escape) in escapes {
+        let position = position.min(end);
+        truncated.push_str(&text[last..position]);
+        truncated.push_str(&escape);
+        last = position;
+    }
+    truncated.push_str(&text[last..end]);
+    Some(truncated)
+}
+
+/// Splits the escape sequences out of `line`, returning the text without them
+/// and each run of sequences along with the position in that text it was at.
+pub fn split_escapes(line: &str) -> (String, Vec<(usize, String)>) {
+    let mut text = Vec::with_capacity(line.len());
+    let mut escapes: Vec<(usize, Vec<u8>)> = Vec::new();
+    let mut sequence = Vec::new();
+    for &b in line.as_bytes() {
+        if sequence.is_empty() && b != ESC {
+            text.push(b);
+            continue;
+        }
+        sequence.push(b);
+        // A sequence cut off by the end of the line is kept as it is.
+        if is_complete(&sequence) {
+            match escapes.last_mut() {
+                Some((at, escape)) if *at == text.len() => escape.append(&mut sequence),
+                _ => escapes.push((text.len(), std::mem::take(&mut sequence))),
+            }
+        }
+    }
+    if !sequence.is_empty() {
+        escapes.push((text.len(), sequence));
+    }
+    // Sequences are ASCII apart from OSC text, so the text between them is
+    // still valid UTF-8.
+    let string = |bytes: Vec<u8>| String::from_utf8_lossy(&bytes).into_owned();
+    (
+        string(text),
+        escapes
+            .into_iter()
+            .map(|(at, escape)| (at, string(escape)))
+            .collect(),
+    )
+}
+
+/// Returns whether `sequence`, which starts with ESC, is a whole escape
+/// sequence. Only CSI (`ESC [`) and OSC (`ESC ]`) sequences are longer than two
+/// bytes.
+fn is_complete(sequence: &[u8]) -> bool {
+    match sequence {
+        [_] | [_, b'['] | [_, b']'] => false,
+        [_, b'[', .., last] => (0x40..=0x7e).contains(last),
+        [_, b']', .., ESC, b'\\'] | [_, b']', .., BEL] => true,
+        [_, b']', ..] => false,
+        _ => true,
+    }
+}
+
+impl<W: Write> Write for Filter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.options.is_noop() {
+            return self.inner.write(buf);
+        }
+
+        let mut out = Vec::with_capacity(buf.len());
+        for &b in buf {
+            if self.sequence.is_empty() && b != ESC {
+                if self.options.compact {
+                    self.sync(&mut out);
+                }
+                self.text(b, &mut out);
+                continue;
+            }
+            self.sequence.push(b);
+            if is_complete(&self.sequence) {
+                let sequence = std::mem::take(&mut self.sequence);
+                let start = out.len();
+                self.rewrite(&sequence, &mut out);
+                if self.options.wrap_width.is_some() {
+                    self.track(&sequence, &out[start..]);
+                }
+            }
+        }
+        self.inner.write_all(&out)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Anything left over isn't a complete sequence, so pass it on as is.
+        let mut sequence = std::mem::take(&mut self.sequence);
+        if self.options.compact {
+            let mut out = Vec::new();
+            self.sync(&mut out);
+            out.append(&mut sequence);
+            sequence = out;
+        }
+        self.inner.write_all(&sequence)?;
+        self.inner.flush()
+    }
+}
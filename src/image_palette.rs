@@ -0,0 +1,85 @@
+//! Picking a palette out of an image for --palette-from, so that the default
+//! colors can match a theme or a wallpaper.
+
+use crate::ansi::Rgb;
+use anyhow::{Context, Result};
+use std::{cmp::Reverse, path::Path};
+
+/// How big a copy of the image the colors are picked from. Its main colors
+/// survive shrinking, and there are far fewer pixels to sort.
+const THUMBNAIL_SIZE: u32 = 128;
+
+/// Returns up to `n` colors that stand in for the image at `path`, most
+/// common first.
+pub fn from_image(path: &Path, n: usize) -> Result<Vec<Rgb>> {
+    let mut image = image::open(path).context(format!("failed to open {}", path.display()))?;
+    if image.width() > THUMBNAIL_SIZE || image.height() > THUMBNAIL_SIZE {
+        image = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+    }
+    let pixels = image.to_rgb8().pixels().map(|pixel| pixel.0).collect();
+    Ok(median_cut(pixels, n))
+}
+
+/// Splits `pixels` into up to `n` boxes by median cut, and returns the
+/// average color of each, the one standing in for the most pixels first.
+/// Each time, the box with the widest spread in any one channel is sorted by
+/// that channel and split in half, until there are `n` boxes or every box is
+/// a single color. Boxes that average out to the same color count as one.
+pub fn median_cut(pixels: Vec<[u8; 3]>, n: usize) -> Vec<Rgb> {
+    if pixels.is_empty() {
+        return Vec::new();
+    }
+    let mut boxes = vec![pixels];
+    while boxes.len() < n {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, pixels)| {
+                let (channel, spread) = widest_channel(pixels);
+                (i, channel, spread)
+            })
+            .max_by_key(|(_, _, spread)| *spread);
+        let Some((i, channel, spread)) = widest else {
+            break;
+        };
+        if spread == 0 {
+            break;
+        }
+        let mut pixels = boxes.swap_remove(i);
+        pixels.sort_unstable_by_key(|pixel| pixel[channel]);
+        let upper = pixels.split_off(pixels.len() / 2);
+        boxes.push(pixels);
+        boxes.push(upper);
+    }
+    let mut colors: Vec<(Rgb, usize)> = Vec::new();
+    for pixels in &boxes {
+        let color = average(pixels);
+        match colors.iter_mut().find(|(other, _)| *other == color) {
+            Some((_, count)) => *count += pixels.len(),
+            None => colors.push((color, pixels.len())),
+        }
+    }
+    colors.sort_by_key(|(_, count)| Reverse(*count));
+    colors.into_iter().map(|(color, _)| color).collect()
+}
+
+/// Returns the channel the pixels are most spread out in, and by how much.
+fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let values = pixels.iter().map(|pixel| pixel[channel]);
+            let spread = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+            (channel, spread)
+        })
+        .max_by_key(|(_, spread)| *spread)
+        .unwrap_or((0, 0))
+}
+
+fn average(pixels: &[[u8; 3]]) -> Rgb {
+    let len = pixels.len().max(1) as u64;
+    let sum = |channel: usize| {
+        let total: u64 = pixels.iter().map(|pixel| pixel[channel] as u64).sum();
+        (total / len) as u8
+    };
+    (sum(0), sum(1), sum(2))
+}
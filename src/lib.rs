@@ -0,0 +1,6597 @@
+//! Recolor the output of any command. This is the library behind the
+//! `recolor` binary, exposed so that the whole pipeline can be run over
+//! in-memory strings with [`process_str`].
+
+mod ansi;
+#[cfg(feature = "image-palette")]
+mod image_palette;
+mod svg;
+#[cfg(feature = "tui")]
+mod tui;
+
+use anyhow::{Context, Result};
+use clap::{CommandFactory, Parser, ValueEnum};
+use lazy_static::lazy_static;
+use log::debug;
+use owo_colors::{self, AnsiColors, DynColors, Effect};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    fs::File,
+    io::{self, stdin, stdout, BufRead, BufReader, BufWriter, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitCode, Stdio},
+    time::{Duration, Instant},
+};
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Parser, Clone, Debug, Default)]
+#[command(author, version)]
+/// Recolor the output of any command by piping it to recolor.
+/// See https://github.com/samwho/recolor for examples.
+pub struct Args {
+    /// A regular expression to match each line of the output piped to this
+    /// program against. Each capture group will be styled with the color
+    /// corresponding to the group name, or a default color based on the capture
+    /// group index if the group has no name.
+    #[arg(required_unless_present_any = ["words", "rules", "urls", "color_test", "print_escape"])]
+    regex: Option<String>,
+
+    /// The rest of the arguments are key=value pairs, where the key is the name
+    /// or number of the capture group, and the value is a comma-separated list
+    /// of styles to apply to that capture group. The styles are applied in
+    /// order, so `bold,red` will make the text bold and red, while `red,green`
+    /// will make the text green.
+    #[arg()]
+    styles: Vec<String>,
+
+    /// Replace the timestamp at the start of each line with the time elapsed
+    /// since the previous timestamped line, e.g. `+0.012s`. Lines that don't
+    /// start with a timestamp are left as they are.
+    #[arg(long)]
+    timestamp_relative: bool,
+
+    /// The regular expression used to find timestamps for
+    /// `--timestamp-relative`. It must have `hour`, `minute` and `second`
+    /// named groups, and can optionally have `year`, `month`, `day` and
+    /// `fraction` groups. Only matches at the start of a line are used.
+    #[arg(long, requires = "timestamp_relative")]
+    timestamp_pattern: Option<String>,
+
+    /// The style to apply to the relative timestamps written by
+    /// `--timestamp-relative`.
+    #[arg(long, default_value = "cyan")]
+    timestamp_style: String,
+
+    /// Build the regex with multi-line mode enabled, so `^` and `$` match at
+    /// newlines inside the text being matched as well as at its start and
+    /// end. Equivalent to starting the regex with `(?m)`.
+    #[arg(long)]
+    multi_line_anchors: bool,
+
+    /// Match the regex against each line joined with the N - 1 lines after
+    /// it, so that matches can cross line boundaries. Only matches starting
+    /// on the first line count, and ones overlapping a match that started on
+    /// an earlier line are skipped. Use --multi-line-anchors for `^` and `$`
+    /// to match at the start and end of every line.
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = [
+            "bytes",
+            "replace",
+            "columns",
+            "max_line_length",
+            "timestamp_relative",
+            "trim",
+            "squeeze_blank",
+        ]
+    )]
+    window: Option<usize>,
+
+    /// With --window, only style the part of each group before its first
+    /// line break, to mark where multi-line blocks start. `first_line_only`
+    /// does the same for a single group's style.
+    #[arg(long, requires = "window")]
+    first_line_only: bool,
+
+    /// Ignore whitespace in the regex and allow `#` comments, so long
+    /// patterns can be spread out and annotated. Use `\ ` or `[ ]` to match a
+    /// literal space.
+    #[arg(short = 'x', long)]
+    extended: bool,
+
+    /// Style the text between a match of the START regex and the next match
+    /// of the END regex, not including the markers themselves. Can be given
+    /// more than once. A START without a following END is left unstyled.
+    #[arg(long, num_args = 3, value_names = ["START", "END", "STYLE"])]
+    between: Vec<String>,
+
+    /// Style GROUP with the style COMMAND prints when it's run by the shell
+    /// with the text the group matched on stdin. It's only run once for each
+    /// different text. Can be given more than once.
+    #[arg(long, num_args = 2, value_names = ["GROUP", "COMMAND"], conflicts_with = "bytes")]
+    style_cmd: Vec<String>,
+
+    /// Let group styles be read from environment variables, with a style of
+    /// `$NAME` or `${NAME}`, or `${NAME:-STYLE}` to use STYLE when NAME isn't
+    /// set or is empty. The variable's value is used as it is, so it can't
+    /// refer to other variables.
+    #[arg(long)]
+    capture_env: bool,
+
+    /// Reject styles with attributes that could hide text, for when styles
+    /// come from somewhere that can't be trusted, like a --style-cmd that
+    /// reads them from its input. This covers group styles and the styles
+    /// given to options like --line-style. --unsafe-attributes sets which
+    /// attributes are rejected.
+    #[arg(long)]
+    safe_styles: bool,
+
+    /// The attributes --safe-styles rejects.
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        value_name = "ATTRIBUTES",
+        default_values_t = [
+            UnsafeAttribute::Hidden,
+            UnsafeAttribute::Blink,
+            UnsafeAttribute::Background,
+        ],
+        requires = "safe_styles"
+    )]
+    unsafe_attributes: Vec<UnsafeAttribute>,
+
+    /// Match the regex case-insensitively.
+    #[arg(short = 'i', long)]
+    ignore_case: bool,
+
+    /// Highlight a literal word, given as word=style. Can be given more than
+    /// once, and is used instead of a regex. Only whole words match, though
+    /// words that start or end with punctuation, like `c++`, can be next to
+    /// anything on that side.
+    #[arg(long = "word", value_name = "WORD=STYLE", conflicts_with = "regex")]
+    words: Vec<String>,
+
+    /// Only style lines that this regex also matches. Other lines are still
+    /// written out, just without any styles.
+    #[arg(long, value_name = "REGEX")]
+    only_when: Option<String>,
+
+    /// Ignore matches where any of the regex's groups didn't capture
+    /// anything, like an optional group that wasn't there.
+    #[arg(long)]
+    require_all_groups: bool,
+
+    /// Only style groups that have a name, leaving groups that are only there
+    /// to structure the regex alone, even if there's a default color for them.
+    #[arg(long)]
+    named_only: bool,
+
+    /// Stop styling groups on a line after this many, so that a regex with a
+    /// lot of matches on a very long line can't slow everything down. A
+    /// warning is printed the first time it happens.
+    #[arg(long, value_name = "N", default_value_t = 10000)]
+    max_groups_per_line: usize,
+
+    /// Replace every match with this template. `$1` or `${1}` is the text of
+    /// group 1, `$name` or `${name}` the text of a named group, and `$$` a
+    /// literal `$`, so `$${name}` is a literal `${name}`. Groups are styled as
+    /// usual wherever they appear.
+    #[arg(long, value_name = "TEMPLATE", conflicts_with_all = ["between", "bytes"])]
+    replace: Option<String>,
+
+    /// Only write the parts of lines that the regex matched, each followed by
+    /// --group-separator, like `grep -o`.
+    #[arg(
+        short = 'o',
+        long,
+        conflicts_with_all = [
+            "replace",
+            "window",
+            "with_filename",
+            "prefix",
+            "suffix",
+            "timestamp_relative",
+            "show_all",
+            "no_trailing_newline",
+        ]
+    )]
+    only_matching: bool,
+
+    /// Change the case of the text that groups match: all upper case, all
+    /// lower case, or title case, with the first letter of each word upper
+    /// case and the rest lower case.
+    #[arg(long, value_enum, value_name = "CASE", conflicts_with = "bytes")]
+    normalize_case: Option<Case>,
+
+    /// What to write after each match with --only-matching. `\t`, `\n`, `\0`
+    /// and `\\` are a tab, a newline, a null byte and a backslash.
+    #[arg(
+        long,
+        value_name = "SEPARATOR",
+        default_value = "\\n",
+        requires = "only_matching"
+    )]
+    group_separator: String,
+
+    /// On lines with a match, replace each run of text outside the matches
+    /// with PLACEHOLDER, `…` by default, leaving the matches and their styles.
+    #[arg(
+        long,
+        value_name = "PLACEHOLDER",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "…",
+        conflicts_with_all = ["only_matching", "replace", "window", "max_width"]
+    )]
+    collapse_unmatched: Option<String>,
+
+    /// Leave out lines without a match, along with --collapse-unmatched.
+    #[arg(
+        long,
+        requires = "collapse_unmatched",
+        conflicts_with_all = [
+            "with_filename",
+            "prefix",
+            "timestamp_relative",
+            "no_trailing_newline",
+            "animate",
+        ]
+    )]
+    drop_unmatched_lines: bool,
+
+    /// Collapse runs of consecutive empty lines into a single empty line.
+    #[arg(short = 's', long)]
+    squeeze_blank: bool,
+
+    /// Strip whitespace from the start and end of every line before matching
+    /// it. The trimmed line is what gets written out, so indentation is lost.
+    /// Lines that are only whitespace become empty, and can be squeezed by
+    /// --squeeze-blank.
+    #[arg(long)]
+    trim: bool,
+
+    /// Read the text to recolor from this file instead of stdin. Can be given
+    /// more than once to read several files in turn.
+    #[arg(long, value_name = "FILE")]
+    input: Vec<PathBuf>,
+
+    /// Read and write in chunks of this many bytes. Output is otherwise
+    /// written a line at a time.
+    #[arg(long, value_name = "BYTES")]
+    buffer_size: Option<usize>,
+
+    /// Read key=value style pairs from stdin, one per line, in addition to
+    /// any given as arguments. Requires `--input`, as stdin is no longer
+    /// available for the text to recolor.
+    #[arg(long, requires = "input")]
+    styles_stdin: bool,
+
+    /// Instead of styling matches, wrap them in `«name:...»` markers showing
+    /// the name, or index, of the group that matched. Useful for debugging a
+    /// regex.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Print the name, or index, and byte range of every capture to stderr.
+    #[arg(long)]
+    debug_matches: bool,
+
+    /// Exit with status 1 if the regex matched fewer than this many times
+    /// across all of the input.
+    #[arg(long, value_name = "N")]
+    min_matches: Option<usize>,
+
+    /// Don't write anything, just exit with status 1 if nothing matched, or
+    /// fewer than --min-matches did, like `grep -q`.
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Read key=value style pairs from a file, one per line. Blank lines and
+    /// lines starting with `#` are ignored. Can be given more than once, and
+    /// styles from later files take precedence over ones from earlier files.
+    /// Styles given as arguments take precedence over all of them.
+    #[arg(long, value_name = "FILE")]
+    styles_file: Vec<PathBuf>,
+
+    /// Read the regex and its styles from a file. The first line that isn't
+    /// blank or a comment is the regex, and the rest is like a styles file.
+    /// Any arguments are styles that take precedence over the file's.
+    #[arg(long, value_name = "FILE", conflicts_with = "words")]
+    rules: Option<PathBuf>,
+
+    /// Highlight URLs with the given style, and make each one a hyperlink to
+    /// itself in terminals that support them. Used instead of a regex. With
+    /// --output svg, URLs are only highlighted, as images can't be clicked.
+    #[arg(
+        long,
+        value_name = "STYLE",
+        num_args = 0..=1,
+        default_missing_value = "blue,underline",
+        conflicts_with_all = ["regex", "words", "rules", "bytes"]
+    )]
+    urls: Option<String>,
+
+    /// Cycle through the default colors for unstyled groups starting from the
+    /// end of the palette instead of the beginning.
+    #[arg(long)]
+    reverse_palette: bool,
+
+    /// Start cycling through the default colors for unstyled groups N colors
+    /// further along the palette, to tell apart the output of more than one
+    /// recolor.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    palette_offset: usize,
+
+    /// Comma-separated colors to cycle through for unstyled groups instead of
+    /// the default ones, e.g. `#f00,#0f0,#00f`. Takes precedence over the
+    /// palette in the config file.
+    #[arg(long, value_name = "COLORS")]
+    default_palette: Option<String>,
+
+    /// Cycle through the main colors of this image for unstyled groups
+    /// instead of the default ones, so that they match a theme or wallpaper.
+    /// Needs the `image-palette` feature.
+    #[cfg(feature = "image-palette")]
+    #[arg(
+        long,
+        value_name = "IMAGE",
+        conflicts_with_all = ["default_palette", "no_default_color", "fallback_style"]
+    )]
+    palette_from: Option<PathBuf>,
+
+    /// How many colors --palette-from picks out of the image.
+    #[cfg(feature = "image-palette")]
+    #[arg(long, value_name = "N", default_value_t = 8, requires = "palette_from")]
+    palette_size: usize,
+
+    /// Leave groups without a style plain, instead of giving them one of the
+    /// default colors.
+    #[arg(long, conflicts_with_all = ["default_palette", "reverse_palette", "palette_offset"])]
+    no_default_color: bool,
+
+    /// Give every group without a style this one, instead of cycling through
+    /// the default colors, e.g. `dimmed` to keep them in the background.
+    #[arg(
+        long,
+        value_name = "STYLE",
+        conflicts_with_all = [
+            "default_palette",
+            "reverse_palette",
+            "palette_offset",
+            "no_default_color",
+        ]
+    )]
+    fallback_style: Option<String>,
+
+    /// Pick the default color for a named group from its name instead of its
+    /// position, so that a name always gets the same color whatever order the
+    /// groups are in. Unnamed groups still go by position.
+    #[arg(long)]
+    color_by_name: bool,
+
+    /// Cut matches longer than the width of their `pad(N)` or `rpad(N)` style
+    /// down to that width, instead of leaving them as they are.
+    #[arg(long)]
+    truncate_padded: bool,
+
+    /// A style to give the whole of every line the regex matches, with the
+    /// styles of any groups layered on top. Lines that don't match are left
+    /// as they are.
+    #[arg(long, value_name = "STYLE")]
+    line_style: Option<String>,
+
+    /// Give every line in a range of line numbers a style, e.g.
+    /// `10:20=on_yellow`. Either end of the range can be left off, and this
+    /// can be given more than once. --line-style takes precedence on lines
+    /// that match.
+    #[arg(long, value_name = "START:END=STYLE")]
+    line_range: Vec<String>,
+
+    /// Give lines that are exact repeats of an earlier line the style from
+    /// --duplicate-style. --line-style takes precedence on lines that match.
+    #[arg(long)]
+    highlight_duplicates: bool,
+
+    /// Only count a line as a duplicate if it repeats the line just before
+    /// it, like `uniq -d`.
+    #[arg(long, requires = "highlight_duplicates")]
+    consecutive_only: bool,
+
+    /// The style to apply to lines found by --highlight-duplicates.
+    #[arg(long, value_name = "STYLE", default_value = "on_bright_black")]
+    duplicate_style: String,
+
+    /// Text to write at the start of every line.
+    #[arg(long, value_name = "TEXT")]
+    prefix: Option<String>,
+
+    /// The style to apply to the text given by `--prefix`.
+    #[arg(long, value_name = "STYLE", requires = "prefix")]
+    prefix_style: Option<String>,
+
+    /// Text to write at the end of every line.
+    #[arg(long, value_name = "TEXT")]
+    suffix: Option<String>,
+
+    /// The style to apply to the text given by `--suffix`.
+    #[arg(long, value_name = "STYLE", requires = "suffix")]
+    suffix_style: Option<String>,
+
+    /// Write a checksum of each line, as it was read, next to it, to tell
+    /// whether the text of two lines is the same however they're colored.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ALGORITHM",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "crc32",
+        conflicts_with = "only_matching"
+    )]
+    checksum: Option<Checksum>,
+
+    /// Where to write the checksums from --checksum.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POSITION",
+        default_value_t = ChecksumPosition::Start,
+        requires = "checksum"
+    )]
+    checksum_position: ChecksumPosition,
+
+    /// The style to apply to the checksums from --checksum.
+    #[arg(long, default_value = "dimmed")]
+    checksum_style: String,
+
+    /// Don't write a newline after the last line of each input, e.g. for
+    /// coloring part of a shell prompt.
+    #[arg(long)]
+    no_trailing_newline: bool,
+
+    /// Show one line at a time, each one replacing the one before, like the
+    /// frames of an animation.
+    #[arg(long, conflicts_with_all = ["only_matching", "no_trailing_newline", "output"])]
+    animate: bool,
+
+    /// Write the lines of each input in reverse order, last line first, like
+    /// `tac`. Nothing is written until the whole input has been read, and all
+    /// of it is kept in memory until then.
+    #[arg(
+        long,
+        visible_alias = "tac",
+        conflicts_with_all = ["animate", "only_matching", "no_trailing_newline", "output"]
+    )]
+    reverse_lines: bool,
+
+    /// How many lines a second to show with --animate.
+    #[arg(long, default_value_t = 10.0, requires = "animate")]
+    fps: f64,
+
+    /// Start every line with the name of the file it came from.
+    #[arg(short = 'H', long)]
+    with_filename: bool,
+
+    /// The style to apply to the file names written by `--with-filename`.
+    #[arg(long, value_name = "STYLE", default_value = "magenta")]
+    filename_style: String,
+
+    /// Only style matches that lie entirely within these columns, given as
+    /// `START:END` like `cut -c`: counting from 1, including both ends. Either
+    /// end can be left off, e.g. `10:` for everything from column 10 onwards.
+    /// With --bytes, columns are bytes rather than characters.
+    #[arg(long, value_name = "START:END")]
+    columns: Option<String>,
+
+    /// Count --columns as they're shown, with tabs reaching to the next
+    /// multiple of this many columns, instead of counting each character as
+    /// one column. The tabs themselves are written unchanged.
+    #[arg(long, value_name = "WIDTH", requires = "columns")]
+    tab_stop: Option<usize>,
+
+    /// Cut lines longer than this many characters short before matching
+    /// them, and end them with an ellipsis. Escape sequences in the input
+    /// don't count towards the length, and are never cut in half.
+    #[arg(long, value_name = "N")]
+    max_line_length: Option<usize>,
+
+    /// Style the part of each line past this many characters, to flag lines
+    /// that are too long. Matches in it are styled on top.
+    #[arg(long, value_name = "N")]
+    max_width: Option<usize>,
+
+    /// The style to apply to the part of a line past --max-width.
+    #[arg(long, value_name = "STYLE", default_value = "on_red")]
+    max_width_style: String,
+
+    /// Draw lines without a match in shades of grey that get darker the more
+    /// lines there have been since the last one with a match, reaching the
+    /// darkest after N lines. Lines before the first match are the darkest.
+    #[arg(long, value_name = "N")]
+    fade: Option<usize>,
+
+    /// Print a line showing each group in the style it's rendered in before
+    /// any output.
+    #[arg(long)]
+    legend: bool,
+
+    /// Show spaces as `·` and tabs as `→`, dimmed, so that they stand out.
+    /// Matches are styled as usual.
+    #[arg(long)]
+    highlight_whitespace: bool,
+
+    /// Don't let a style start or stop partway through a character made of
+    /// more than one code point, like a letter with a combining accent or an
+    /// emoji with a skin tone. Styles that would are moved to the end of it.
+    #[arg(long, conflicts_with = "bytes")]
+    whole_graphemes: bool,
+
+    /// Like --highlight-whitespace, but also mark the end of every line with
+    /// a dimmed `$`, like `cat -A`.
+    #[arg(long)]
+    show_all: bool,
+
+    /// Reset all styles before writing anything, in case the terminal has been
+    /// left with some styles turned on.
+    #[arg(long)]
+    reset_on_start: bool,
+
+    /// Periodically print how many lines have been processed, and how fast,
+    /// to stderr.
+    #[arg(long)]
+    progress: bool,
+
+    /// At the end, print how many bytes of the input the regex matched, in
+    /// total and for each group, to stderr.
+    #[arg(long, conflicts_with = "count_chars")]
+    count_bytes: bool,
+
+    /// Like --count-bytes, but counting characters.
+    #[arg(long, conflicts_with = "bytes")]
+    count_chars: bool,
+
+    /// At the end, print a histogram of how long each group's matches were,
+    /// in characters, to stderr.
+    #[arg(long)]
+    stats: bool,
+
+    /// At the end, print how long was spent reading input, matching the
+    /// regex, working out styles and writing output to stderr, to find out
+    /// what's slow.
+    #[arg(long)]
+    profile: bool,
+
+    /// Match the regex against raw bytes instead of text, so that input that
+    /// isn't valid UTF-8 is passed through untouched. The regex can match
+    /// arbitrary bytes with escapes like `(?-u:\xff)`. Options that count
+    /// characters, like --max-line-length and --columns, count bytes instead.
+    #[arg(long, conflicts_with_all = ["timestamp_relative", "between"])]
+    bytes: bool,
+
+    /// Skip lines that aren't valid UTF-8, with a warning on stderr, instead
+    /// of stopping. Other errors reading the input still stop recolor, as
+    /// there's no next line to carry on with.
+    #[arg(long, conflicts_with = "bytes")]
+    keep_going: bool,
+
+    /// Wrap escape sequences in tmux's passthrough sequence, so that they
+    /// reach the outer terminal from inside nested tmux sessions.
+    #[arg(long)]
+    tmux_passthrough: bool,
+
+    /// Turn styles back on at the start of each row that the terminal wraps
+    /// a long line onto, for terminals that lose them there. The width is
+    /// asked of the terminal, or taken from `COLUMNS` when the output isn't
+    /// a terminal.
+    #[arg(long, conflicts_with = "output")]
+    repeat_style_on_wrap: bool,
+
+    /// Make the output smaller by only writing the styles that change from
+    /// one piece of text to the next, instead of turning every style on and
+    /// off again around each one. It looks the same in a terminal.
+    #[arg(long, conflicts_with = "repeat_style_on_wrap")]
+    compact_escapes: bool,
+
+    /// Style text that's next to text with the same style, like two matches
+    /// of the same group side by side, once for both instead of separately.
+    #[arg(long)]
+    merge_adjacent_same_style: bool,
+
+    /// Write OPEN before and CLOSE after each match, given as `OPEN,CLOSE`,
+    /// so that tools that can't read colors can still find them. Defaults to
+    /// `<<,>>`. Matches are still styled too, unless `--color never` is
+    /// given.
+    #[arg(
+        long,
+        value_name = "OPEN,CLOSE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "<<,>>",
+        conflicts_with = "dry_run"
+    )]
+    delimit_matches: Option<String>,
+
+    /// Delimiters to use around one group's matches instead of those given to
+    /// `--delimit-matches`, as `GROUP=OPEN,CLOSE`. Can be given more than
+    /// once.
+    #[arg(long, value_name = "GROUP=OPEN,CLOSE", requires = "delimit_matches")]
+    group_delimiters: Vec<String>,
+
+    /// Wrap each match in Unicode directional isolates, so that terminals
+    /// that lay out right-to-left text keep it in one piece, in its own
+    /// direction, instead of mixing it in with the text around it.
+    #[arg(long, conflicts_with = "delimit_matches")]
+    bidi_isolate: bool,
+
+    /// What to do with OSC escape sequences in the input, like the ones that
+    /// set the terminal's title.
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = ansi::OscMode::Pass)]
+    osc: ansi::OscMode,
+
+    /// Match the regex against each line with its escape sequences taken
+    /// out, so that colors already in the input don't get in the way, and put
+    /// them back in the output with recolor's styles on top.
+    #[arg(long, conflicts_with_all = ["bytes", "window", "replace", "trim"])]
+    ignore_ansi_in_matching: bool,
+
+    /// Only use the 16 standard colors, replacing any others with the closest
+    /// standard one, for terminals that can't show more.
+    #[arg(long, visible_alias = "16-color")]
+    ascii_only: bool,
+
+    /// How many colors the terminal can show. Styles using colors it can't
+    /// show are changed to use the closest color it can. Detected from
+    /// `COLORTERM` and `TERM` when writing to a terminal.
+    #[arg(long, value_enum, value_name = "DEPTH", conflicts_with = "ascii_only")]
+    colors: Option<ansi::ColorDepth>,
+
+    /// What to write: text with ANSI escape sequences, an SVG image of what
+    /// that text looks like in a terminal, or a JSON object for each line
+    /// describing what each group matched in it.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value_t = OutputFormat::Ansi,
+        conflicts_with = "tmux_passthrough"
+    )]
+    output: OutputFormat,
+
+    /// Which style to use for text inside more than one group: the innermost
+    /// group's, the outermost group's, or all of them layered on top of each
+    /// other.
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = StyleMode::Innermost)]
+    style_mode: StyleMode,
+
+    /// When to style the output. `auto` only styles it when writing to a
+    /// terminal.
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Always)]
+    color: ColorChoice,
+
+    /// The width of the terminal for --repeat-style-on-wrap, set by main.
+    #[arg(skip)]
+    terminal_width: Option<usize>,
+
+    /// What to call groups in --legend, --dry-run and the like instead of
+    /// their names, set for --word.
+    #[arg(skip)]
+    group_labels: HashMap<String, String>,
+
+    /// Don't read the config file.
+    #[arg(long)]
+    no_config: bool,
+
+    /// Print what recolor knows about the terminal and how many colors it
+    /// thinks it can show, with a swatch of colors at each depth, then exit.
+    #[arg(long)]
+    color_test: bool,
+
+    /// Print the escape sequence that turns on the style for KEY, and the one
+    /// that turns it off again, each on their own line, then exit. Handy for
+    /// putting recolor's styles in a shell prompt. All of the arguments are
+    /// styles, as there's no regex.
+    #[arg(long, value_name = "KEY")]
+    print_escape: Option<String>,
+
+    /// Print the sequences from --print-escape with `\e` in place of the
+    /// escape character, for `echo -e` and `printf`.
+    #[arg(long, requires = "print_escape")]
+    escaped: bool,
+
+    /// Show the start of the input colored with the regex and styles, and
+    /// update it as they're edited. Tab switches between the regex and the
+    /// styles, Enter prints the command line for them and Esc quits.
+    #[cfg(feature = "tui")]
+    #[arg(long, conflicts_with_all = ["words", "urls", "output"])]
+    tui: bool,
+
+    /// Settings from the config file, set by main.
+    #[arg(skip)]
+    config: Config,
+}
+
+/// Defaults read from `config.toml` in the recolor config directory, e.g.
+/// `~/.config/recolor/config.toml` on Linux.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    /// key=value styles, which styles from the command line or
+    /// `--styles-file` take precedence over.
+    styles: Vec<String>,
+    /// Styles to cycle through for unstyled groups instead of the default
+    /// colors.
+    palette: Vec<String>,
+}
+
+/// Reads the config file at `path`. A missing file isn't an error, it just
+/// means there's nothing to configure.
+fn load_config(path: &Path) -> Result<Config> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(err) => return Err(err).context(format!("failed to read {}", path.display())),
+    };
+    toml::from_str(&contents).context(format!("invalid config file {}", path.display()))
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+enum ColorChoice {
+    #[default]
+    Always,
+    Never,
+    Auto,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+enum OutputFormat {
+    #[default]
+    Ansi,
+    Svg,
+    Ndjson,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Case {
+    Upper,
+    Lower,
+    Title,
+}
+
+/// Returns `text` in `case`. Characters whose other case takes up a
+/// different number of bytes, like `ẞ` and `ß`, are left alone, so that
+/// everything after them stays where it was.
+fn change_case(text: &str, case: Case) -> String {
+    let mut changed = String::with_capacity(text.len());
+    let mut word_start = true;
+    for c in text.chars() {
+        let upper = match case {
+            Case::Upper => true,
+            Case::Lower => false,
+            Case::Title => word_start,
+        };
+        word_start = !c.is_alphanumeric();
+        let mapped: String = if upper {
+            c.to_uppercase().collect()
+        } else {
+            c.to_lowercase().collect()
+        };
+        if mapped.len() == c.len_utf8() {
+            changed.push_str(&mapped);
+        } else {
+            changed.push(c);
+        }
+    }
+    changed
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Checksum {
+    Crc32,
+    Fnv1a,
+}
+
+impl Checksum {
+    /// Returns the checksum of `bytes` as 8 hex digits.
+    fn of(&self, bytes: &[u8]) -> String {
+        let hash = match self {
+            Checksum::Crc32 => {
+                let mut crc = !0u32;
+                for byte in bytes {
+                    crc ^= *byte as u32;
+                    for _ in 0..8 {
+                        crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+                    }
+                }
+                !crc
+            }
+            Checksum::Fnv1a => bytes.iter().fold(0x811c9dc5u32, |hash, byte| {
+                (hash ^ *byte as u32).wrapping_mul(0x01000193)
+            }),
+        };
+        format!("{:08x}", hash)
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+enum ChecksumPosition {
+    #[default]
+    Start,
+    End,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+enum StyleMode {
+    #[default]
+    Innermost,
+    Outermost,
+    Stack,
+}
+
+lazy_static! {
+    static ref DEFAULT_STYLES: Vec<Style> = {
+        vec![
+            owo_colors::Style::new().red().into(),
+            owo_colors::Style::new().green().into(),
+            owo_colors::Style::new().yellow().into(),
+            owo_colors::Style::new().blue().into(),
+            owo_colors::Style::new().magenta().into(),
+            owo_colors::Style::new().cyan().into(),
+            owo_colors::Style::new().white().into(),
+        ]
+    };
+}
+
+/// The 16 standard ANSI colors, in the order of their SGR codes.
+const ANSI_COLORS: [AnsiColors; 16] = [
+    AnsiColors::Black,
+    AnsiColors::Red,
+    AnsiColors::Green,
+    AnsiColors::Yellow,
+    AnsiColors::Blue,
+    AnsiColors::Magenta,
+    AnsiColors::Cyan,
+    AnsiColors::White,
+    AnsiColors::BrightBlack,
+    AnsiColors::BrightRed,
+    AnsiColors::BrightGreen,
+    AnsiColors::BrightYellow,
+    AnsiColors::BrightBlue,
+    AnsiColors::BrightMagenta,
+    AnsiColors::BrightCyan,
+    AnsiColors::BrightWhite,
+];
+
+lazy_static! {
+    static ref DEFAULT_TIMESTAMP_REGEX: Regex = Regex::new(
+        r"^(?:(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})[T ])?(?P<hour>\d{2}):(?P<minute>\d{2}):(?P<second>\d{2})(?:[.,](?P<fraction>\d+))?(?:Z|[+-]\d{2}:?\d{2})?"
+    )
+    .unwrap();
+}
+
+/// Splits `s` on commas that aren't inside parentheses, so that styles like
+/// `rgb(1,2,3),bold` split into `rgb(1,2,3)` and `bold`.
+fn split_top_level(s: &str) -> Vec<&str> {
+    split_top_level_on(s, ',')
+}
+
+/// Like `split_top_level`, but splits on `separator` instead of commas.
+fn split_top_level_on(s: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            c if c == separator && depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+/// Parses the channels of an `rgb(r, g, b)` color. Channels are either all
+/// numbers from 0 to 255, or all percentages from 0% to 100%.
+fn parse_rgb(body: &str) -> Result<(u8, u8, u8)> {
+    let invalid = || anyhow::anyhow!(format!("invalid rgb color: \"rgb({})\"", body));
+    let channels: Vec<&str> = body.split(',').map(str::trim).collect();
+    if channels.len() != 3 {
+        return Err(invalid());
+    }
+
+    let percentages = channels.iter().filter(|c| c.ends_with('%')).count();
+    let values = match percentages {
+        0 => channels
+            .iter()
+            .map(|c| c.parse::<u8>().map_err(|_| invalid()))
+            .collect::<Result<Vec<u8>>>()?,
+        3 => channels
+            .iter()
+            .map(|c| match c.trim_end_matches('%').parse::<f64>() {
+                Ok(p) if (0.0..=100.0).contains(&p) => Ok((p * 255.0 / 100.0).round() as u8),
+                _ => Err(invalid()),
+            })
+            .collect::<Result<Vec<u8>>>()?,
+        _ => {
+            return Err(anyhow::anyhow!(format!(
+                "invalid rgb color: \"rgb({})\", can't mix percentages and numbers",
+                body
+            )))
+        }
+    };
+    Ok((values[0], values[1], values[2]))
+}
+
+/// Parses a single color, returning `None` if `part` isn't a color at all.
+fn parse_color(part: &str) -> Result<Option<DynColors>> {
+    if let Some(body) = part.strip_prefix("rgb(").and_then(|p| p.strip_suffix(')')) {
+        let (r, g, b) = parse_rgb(body)?;
+        return Ok(Some(DynColors::Rgb(r, g, b)));
+    }
+    if let Some(hex) = part.strip_prefix('#').or_else(|| part.strip_prefix("0x")) {
+        // Expand CSS shorthand, where #f80 means #ff8800.
+        let hex = match hex.len() {
+            3 => hex.chars().flat_map(|c| [c, c]).collect(),
+            _ => hex.to_string(),
+        };
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(anyhow::anyhow!(format!("invalid hex color: \"{}\"", part)));
+        }
+        let (r, g, b) = (
+            u8::from_str_radix(&hex[0..2], 16)?,
+            u8::from_str_radix(&hex[2..4], 16)?,
+            u8::from_str_radix(&hex[4..6], 16)?,
+        );
+        return Ok(Some(DynColors::Rgb(r, g, b)));
+    }
+    let index = part
+        .strip_prefix("ansi(")
+        .and_then(|p| p.strip_suffix(')'))
+        .or_else(|| {
+            part.strip_prefix('a')
+                .filter(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        });
+    if let Some(index) = index {
+        let color = index
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| ANSI_COLORS.get(i))
+            .context(format!(
+                "invalid ANSI color: \"{}\", must be between 0 and 15",
+                part
+            ))?;
+        return Ok(Some(DynColors::Ansi(*color)));
+    }
+
+    let color = match part {
+        "black" => AnsiColors::Black,
+        "red" => AnsiColors::Red,
+        "green" => AnsiColors::Green,
+        "yellow" => AnsiColors::Yellow,
+        "blue" => AnsiColors::Blue,
+        "magenta" => AnsiColors::Magenta,
+        "cyan" => AnsiColors::Cyan,
+        "white" => AnsiColors::White,
+        "bright_black" => AnsiColors::BrightBlack,
+        "bright_red" => AnsiColors::BrightRed,
+        "bright_green" => AnsiColors::BrightGreen,
+        "bright_yellow" => AnsiColors::BrightYellow,
+        "bright_blue" => AnsiColors::BrightBlue,
+        "bright_magenta" => AnsiColors::BrightMagenta,
+        "bright_cyan" => AnsiColors::BrightCyan,
+        "bright_white" => AnsiColors::BrightWhite,
+        _ => return Ok(None),
+    };
+    Ok(Some(DynColors::Ansi(color)))
+}
+
+/// How much of the foreground color a `tinted` style's background has.
+const TINT: f64 = 0.3;
+
+fn parse_style(s: &str) -> Result<Style> {
+    let mut style = Style::new();
+    let mut foreground = None;
+    let mut tinted = false;
+    for part in split_top_level(s) {
+        if let Some(background) = part.strip_prefix("on_") {
+            match parse_color(background)? {
+                Some(color) => style = style.map(|s| s.on_color(color)),
+                None => return Err(anyhow::anyhow!(format!("invalid style: \"{}\"", s))),
+            }
+            continue;
+        }
+        if let Some(color) = parse_color(part)? {
+            style = style.map(|s| s.color(color));
+            foreground = Some(color);
+            continue;
+        }
+        if part == "tinted" {
+            tinted = true;
+            continue;
+        }
+        let effect = match part {
+            "bold" | "bolded" => Effect::Bold,
+            "dimmed" | "dim" | "faint" => Effect::Dimmed,
+            "italic" | "italics" => Effect::Italic,
+            "underline" | "underlined" => Effect::Underline,
+            "blink" | "blinking" => Effect::Blink,
+            // Terminals only have the one "conceal" attribute, SGR 8.
+            "hidden" | "conceal" | "concealed" => Effect::Hidden,
+            "strikethrough" | "struckthrough" | "strike" => Effect::Strikethrough,
+            "framed" => {
+                style.framed = true;
+                continue;
+            }
+            "encircled" => {
+                style.encircled = true;
+                continue;
+            }
+            _ => return Err(anyhow::anyhow!(format!("invalid style: \"{}\"", s))),
+        };
+        style = style.map(|s| s.effect(effect));
+    }
+    if tinted {
+        // A darker version of the foreground, whatever the background was.
+        let (r, g, b) = match foreground {
+            Some(DynColors::Rgb(r, g, b)) => (r, g, b),
+            Some(DynColors::Ansi(color)) => {
+                let i = ANSI_COLORS.iter().position(|c| *c == color).unwrap();
+                ansi::XTERM_COLORS[i]
+            }
+            _ => {
+                return Err(anyhow::anyhow!(format!(
+                    "invalid style: \"{}\", tinted needs a foreground color",
+                    s
+                )))
+            }
+        };
+        let tint = |channel: u8| (channel as f64 * TINT).round() as u8;
+        style = style.map(|s| s.on_truecolor(tint(r), tint(g), tint(b)));
+    }
+    Ok(style)
+}
+
+/// An attribute that can hide or obscure text, which --safe-styles can
+/// reject.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum UnsafeAttribute {
+    /// `hidden` and its aliases.
+    Hidden,
+    /// `blink` and its aliases.
+    Blink,
+    /// Any background color, including from `tinted` and `bg_gradient`.
+    Background,
+}
+
+/// Checks that a style, which can be a group style like `match(...)`, doesn't
+/// use any of the `disallowed` attributes. Only the styles are checked, not
+/// the values a `match` style compares against.
+fn check_safe_style(s: &str, disallowed: &[UnsafeAttribute]) -> Result<()> {
+    let pieces = s.split([',', '|', '(', ')']);
+    for word in pieces.map(|piece| piece.rsplit('=').next().unwrap_or(piece).trim()) {
+        let attribute = match word {
+            "hidden" | "conceal" | "concealed" => UnsafeAttribute::Hidden,
+            "blink" | "blinking" => UnsafeAttribute::Blink,
+            "tinted" | "bg_gradient" => UnsafeAttribute::Background,
+            _ if word.starts_with("on_") => UnsafeAttribute::Background,
+            _ => continue,
+        };
+        if disallowed.contains(&attribute) {
+            return Err(anyhow::anyhow!(format!(
+                "unsafe style: \"{}\", {} isn't allowed with --safe-styles",
+                s, word
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The style to give a capture group, either fixed or chosen based on the
+/// text the group captured.
+#[derive(Clone, Debug, PartialEq)]
+enum GroupStyle {
+    Fixed(Style),
+    Match {
+        cases: Vec<(String, Style)>,
+        default: Style,
+        /// The group whose text is compared against the cases, if it isn't
+        /// the styled group itself.
+        source: Option<String>,
+    },
+    /// Styles to take turns with, one for each time the group matches on a
+    /// line.
+    Cycle(Vec<Style>),
+    /// A style chosen by what kind of text the group matched.
+    Classify {
+        cases: Vec<(TextClass, Style)>,
+        default: Style,
+    },
+    /// A style whose background fades from one color to another across the
+    /// characters of the matched text.
+    BackgroundGradient {
+        style: Style,
+        from: (u8, u8, u8),
+        to: (u8, u8, u8),
+    },
+    /// A style whose color is somewhere between two colors, depending on
+    /// where the number the group matched is between `min` and `max`.
+    Heat {
+        style: Style,
+        min: f64,
+        max: f64,
+        from: (u8, u8, u8),
+        to: (u8, u8, u8),
+    },
+    /// Another style, only applied to the text up to the first newline, for
+    /// matches that cross lines with --window.
+    FirstLineOnly(Box<GroupStyle>),
+    /// Another style, with the matched text padded with spaces to a width.
+    Padded {
+        style: Box<GroupStyle>,
+        padding: Padding,
+    },
+    /// Another style, for a group whose text is replaced with a more readable
+    /// version of the number it matched.
+    Humanized {
+        style: Box<GroupStyle>,
+        humanize: Humanize,
+    },
+}
+
+/// A kind of text, for `classify(...)` styles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TextClass {
+    /// A number, like `42`, `-7` or `3.14`.
+    Num,
+    /// Letters, digits and underscores, with at least one that isn't a
+    /// digit, like an identifier.
+    Word,
+    /// Only whitespace.
+    Space,
+    /// Only ASCII punctuation.
+    Punct,
+}
+
+impl TextClass {
+    fn parse(s: &str) -> Option<TextClass> {
+        match s {
+            "num" => Some(TextClass::Num),
+            "word" => Some(TextClass::Word),
+            "space" => Some(TextClass::Space),
+            "punct" => Some(TextClass::Punct),
+            _ => None,
+        }
+    }
+
+    /// Returns the kind of text `text` is, or `None` if it's empty or a mix.
+    fn of(text: &str) -> Option<TextClass> {
+        let digits = text.strip_prefix(['-', '+']).unwrap_or(text);
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, "0"));
+        let all = |s: &str, f: fn(char) -> bool| !s.is_empty() && s.chars().all(f);
+        if all(whole, |c| c.is_ascii_digit()) && all(fraction, |c| c.is_ascii_digit()) {
+            Some(TextClass::Num)
+        } else if all(text, |c| c.is_alphanumeric() || c == '_') {
+            Some(TextClass::Word)
+        } else if all(text, char::is_whitespace) {
+            Some(TextClass::Space)
+        } else if all(text, |c| c.is_ascii_punctuation()) {
+            Some(TextClass::Punct)
+        } else {
+            None
+        }
+    }
+}
+
+/// How to make a number more readable, from a `humanize(...)` style.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Humanize {
+    /// A number of bytes, like `1.0 MiB`.
+    Bytes,
+    /// A number of seconds, or milliseconds with an `ms` unit, like `1h 2m 5s`.
+    Duration,
+}
+
+impl Humanize {
+    /// Returns `text` made more readable, or `None` if it isn't a number.
+    fn apply(&self, text: &str) -> Option<String> {
+        // Durations can have their unit after them, which the humanized text
+        // replaces along with the number.
+        let (text, scale) = match self {
+            Humanize::Duration => match text.strip_suffix("ms") {
+                Some(text) => (text, 0.001),
+                None => (text.strip_suffix('s').unwrap_or(text), 1.0),
+            },
+            Humanize::Bytes => (text, 1.0),
+        };
+        if text.is_empty() || !text.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            return None;
+        }
+        let n = text.parse::<f64>().ok()? * scale;
+        Some(match self {
+            Humanize::Bytes => {
+                let units = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+                let mut unit = 0;
+                // There's no such thing as part of a byte.
+                let mut n = n.round();
+                while n >= 1024.0 && unit < units.len() - 1 {
+                    n /= 1024.0;
+                    unit += 1;
+                }
+                if unit == 0 {
+                    format!("{} B", n)
+                } else {
+                    format!("{:.1} {}", n, units[unit])
+                }
+            }
+            Humanize::Duration if n < 1.0 => format!("{}ms", (n * 1000.0).round()),
+            Humanize::Duration if n < 60.0 => format!("{}s", (n * 10.0).round() / 10.0),
+            Humanize::Duration => {
+                let mut seconds = n.round() as u64;
+                let mut parts = Vec::new();
+                for (unit, size) in [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)] {
+                    if seconds >= size {
+                        parts.push(format!("{}{}", seconds / size, unit));
+                        seconds %= size;
+                    }
+                }
+                parts.join(" ")
+            }
+        })
+    }
+}
+
+/// How to pad matched text, from a `pad(N)` or `rpad(N)` style.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Padding {
+    /// How many characters wide to make the text.
+    width: usize,
+    /// Pad on the left, lining the text up on the right like `printf %8s`,
+    /// rather than on the right.
+    left: bool,
+    /// Cut text longer than `width` down to it, instead of leaving it alone.
+    truncate: bool,
+}
+
+impl GroupStyle {
+    /// Returns the style for a match whose key, the text that `match` styles
+    /// compare against, is `key`. A missing key gets the default style.
+    /// `occurrence` counts how many times the group has already matched on
+    /// the line.
+    fn resolve(&self, key: Option<&str>, occurrence: usize) -> Style {
+        match self {
+            GroupStyle::Fixed(style) => *style,
+            GroupStyle::Cycle(styles) => styles[occurrence % styles.len()],
+            GroupStyle::Match { cases, default, .. } => cases
+                .iter()
+                .find(|(value, _)| Some(value.as_str()) == key)
+                .map(|(_, style)| *style)
+                .unwrap_or(*default),
+            GroupStyle::Classify { cases, default } => {
+                let class = key.and_then(TextClass::of);
+                cases
+                    .iter()
+                    .find(|(case, _)| Some(*case) == class)
+                    .map(|(_, style)| *style)
+                    .unwrap_or(*default)
+            }
+            GroupStyle::BackgroundGradient { style, .. } => *style,
+            GroupStyle::Heat {
+                style,
+                min,
+                max,
+                from,
+                to,
+            } => match key.and_then(|key| key.trim().parse::<f64>().ok()) {
+                Some(n) if n.is_finite() => {
+                    let (r, g, b) = blend(*from, *to, ((n - min) / (max - min)).clamp(0.0, 1.0));
+                    style.map(|s| s.truecolor(r, g, b))
+                }
+                _ => *style,
+            },
+            GroupStyle::FirstLineOnly(style)
+            | GroupStyle::Padded { style, .. }
+            | GroupStyle::Humanized { style, .. } => style.resolve(key, occurrence),
+        }
+    }
+
+    /// Makes padding cut text that's too long, if this style pads.
+    fn truncate_padding(&mut self) {
+        if let GroupStyle::Padded { padding, .. } = self {
+            padding.truncate = true;
+        }
+    }
+
+    /// How to make the group's text more readable, if this style does.
+    fn humanize(&self) -> Option<Humanize> {
+        match self {
+            GroupStyle::Humanized { humanize, .. } => Some(*humanize),
+            GroupStyle::Padded { style, .. } => style.humanize(),
+            _ => None,
+        }
+    }
+
+    /// The name or index of the group that picks this style, if it isn't the
+    /// group being styled.
+    fn source(&self) -> Option<&str> {
+        match self {
+            GroupStyle::Match { source, .. } => source.as_deref(),
+            GroupStyle::FirstLineOnly(style)
+            | GroupStyle::Padded { style, .. }
+            | GroupStyle::Humanized { style, .. } => style.source(),
+            _ => None,
+        }
+    }
+
+    /// Adds the spans needed to style a group's match.
+    fn push_spans(&self, ops: &mut Ops, m: &GroupMatch, label: &str) {
+        let (start, text) = (m.start, m.text);
+        // The text has already been humanized by the time it's styled.
+        if let GroupStyle::Humanized { style, .. } = self {
+            style.push_spans(ops, m, label);
+            return;
+        }
+        if let GroupStyle::FirstLineOnly(style) = self {
+            let first_line = GroupMatch {
+                text: text.split('\n').next().unwrap_or_default(),
+                ..*m
+            };
+            style.push_spans(ops, &first_line, label);
+            return;
+        }
+        if let GroupStyle::Padded { style, padding } = self {
+            style.push_spans(ops, m, label);
+            let len = text.chars().count();
+            if len < padding.width {
+                let at = if padding.left {
+                    start
+                } else {
+                    start + text.len()
+                };
+                ops.push_padding(at, padding.width - len);
+            } else if let Some((end, _)) = text.char_indices().nth(padding.width) {
+                if padding.truncate {
+                    ops.push_cut(start + end, start + text.len());
+                }
+            }
+            return;
+        }
+        let GroupStyle::BackgroundGradient { style, from, to } = self else {
+            ops.push_span(
+                start,
+                start + text.len(),
+                self.resolve(m.key, m.occurrence),
+                label,
+            );
+            return;
+        };
+
+        // The outer span only exists to carry the label for --dry-run.
+        ops.push_span(start, start + text.len(), *style, label);
+        let steps = text.chars().count().saturating_sub(1).max(1) as f64;
+        for (i, (offset, c)) in text.char_indices().enumerate() {
+            let (r, g, b) = blend(*from, *to, i as f64 / steps);
+            let from = start + offset;
+            ops.push_span(
+                from,
+                from + c.len_utf8(),
+                style.map(|s| s.on_truecolor(r, g, b)),
+                "",
+            );
+        }
+    }
+}
+
+/// Returns the color `t` of the way from `from` to `to`, where `t` is between
+/// 0 and 1.
+fn blend(from: (u8, u8, u8), to: (u8, u8, u8), t: f64) -> (u8, u8, u8) {
+    let channel = |from: u8, to: u8| (from as f64 + (to as f64 - from as f64) * t).round() as u8;
+    (
+        channel(from.0, to.0),
+        channel(from.1, to.1),
+        channel(from.2, to.2),
+    )
+}
+
+/// The text that a group matched, and what's needed to choose its style.
+struct GroupMatch<'a> {
+    /// The byte of the line the text starts at.
+    start: usize,
+    text: &'a str,
+    /// The text that `match` styles compare against.
+    key: Option<&'a str>,
+    /// How many times the group has already matched on the line.
+    occurrence: usize,
+}
+
+/// Parses a color that has to be given as RGB, for things like gradients
+/// that need to mix colors together.
+fn parse_rgb_color(s: &str) -> Result<(u8, u8, u8)> {
+    match parse_color(s.trim())? {
+        Some(DynColors::Rgb(r, g, b)) => Ok((r, g, b)),
+        _ => Err(anyhow::anyhow!(format!(
+            "invalid color: \"{}\", must be a hex or rgb() color",
+            s
+        ))),
+    }
+}
+
+/// Parses the body of a `match(value=style,...)` group style. Each style can
+/// itself be a comma-separated list, so any part without an `=` is added on to
+/// the style of the case before it. `*` sets the style used when no case
+/// matches, otherwise unmatched text is left plain.
+fn parse_match_style(s: &str, source: Option<&str>) -> Result<GroupStyle> {
+    let (cases, default) = parse_cases(s)?;
+    Ok(GroupStyle::Match {
+        cases,
+        default,
+        source: source.map(str::to_string),
+    })
+}
+
+/// Parses the `value=style` cases of a `match` or `classify` style, returning
+/// them along with the style for `*`.
+fn parse_cases(s: &str) -> Result<(Vec<(String, Style)>, Style)> {
+    let mut parts: Vec<(&str, Vec<&str>)> = Vec::new();
+    for part in split_top_level(s) {
+        match part.split_once('=') {
+            Some((value, style)) => parts.push((value, vec![style])),
+            None => parts
+                .last_mut()
+                .context(format!("invalid match style: \"{}\"", s))?
+                .1
+                .push(part),
+        }
+    }
+
+    let mut cases = Vec::new();
+    let mut default = Style::new();
+    for (value, style) in parts {
+        let style = parse_style(&style.join(","))?;
+        if value == "*" {
+            default = style;
+        } else {
+            cases.push((value.to_string(), style));
+        }
+    }
+    Ok((cases, default))
+}
+
+/// Parses the body of a `classify(class=style,...)` group style, which is like
+/// a `match` style with kinds of text instead of values. `other` or `*` sets
+/// the style for text that's a mix of kinds.
+fn parse_classify_style(s: &str) -> Result<GroupStyle> {
+    let (cases, mut default) = parse_cases(s)?;
+    let mut classes = Vec::new();
+    for (class, style) in cases {
+        if class == "other" {
+            default = style;
+            continue;
+        }
+        let class = TextClass::parse(&class).context(format!(
+            "invalid class: \"{}\", must be num, word, space, punct or other",
+            class
+        ))?;
+        classes.push((class, style));
+    }
+    Ok(GroupStyle::Classify {
+        cases: classes,
+        default,
+    })
+}
+
+fn parse_group_style(s: &str) -> Result<GroupStyle> {
+    let alternatives = split_top_level_on(s, '|');
+    if alternatives.len() > 1 {
+        let styles = alternatives
+            .into_iter()
+            .map(parse_style)
+            .collect::<Result<_>>()?;
+        return Ok(GroupStyle::Cycle(styles));
+    }
+    if let Some(body) = s.strip_prefix("match(").and_then(|s| s.strip_suffix(')')) {
+        return parse_match_style(body, None);
+    }
+    if let Some(body) = s
+        .strip_prefix("classify(")
+        .and_then(|s| s.strip_suffix(')'))
+    {
+        return parse_classify_style(body);
+    }
+    // match@group(...) picks the style using another group's text.
+    if let Some(rest) = s.strip_prefix("match@") {
+        if let Some((source, body)) = rest.split_once('(') {
+            if let Some(body) = body.strip_suffix(')') {
+                return parse_match_style(body, Some(source));
+            }
+        }
+        return Err(anyhow::anyhow!(format!("invalid match style: \"{}\"", s)));
+    }
+
+    let mut gradient = None;
+    let mut heat = None;
+    let mut padding = None;
+    let mut humanize = None;
+    let mut first_line_only = false;
+    let mut parts = Vec::new();
+    for part in split_top_level(s) {
+        if part == "first_line_only" {
+            first_line_only = true;
+            continue;
+        }
+        if let Some(kind) = part
+            .strip_prefix("humanize(")
+            .and_then(|p| p.strip_suffix(')'))
+        {
+            humanize = Some(match kind.trim() {
+                "bytes" => Humanize::Bytes,
+                "duration" => Humanize::Duration,
+                _ => return Err(anyhow::anyhow!(format!("invalid humanize: \"{}\"", part))),
+            });
+            continue;
+        }
+        if let Some(body) = part
+            .strip_prefix("bg_gradient(")
+            .and_then(|p| p.strip_suffix(')'))
+        {
+            let [from, to] = split_top_level(body)[..] else {
+                return Err(anyhow::anyhow!(format!("invalid gradient: \"{}\"", part)));
+            };
+            gradient = Some((parse_rgb_color(from)?, parse_rgb_color(to)?));
+            continue;
+        }
+        if let Some(body) = part.strip_prefix("heat(").and_then(|p| p.strip_suffix(')')) {
+            let invalid = || {
+                format!(
+                    "invalid heat: \"{}\", format is heat(MIN,MAX,FROM,TO)",
+                    part
+                )
+            };
+            let [min, max, from, to] = split_top_level(body)[..] else {
+                return Err(anyhow::anyhow!(invalid()));
+            };
+            let (min, max): (f64, f64) = (
+                min.trim().parse().with_context(invalid)?,
+                max.trim().parse().with_context(invalid)?,
+            );
+            if !min.is_finite() || !max.is_finite() || min == max {
+                return Err(anyhow::anyhow!(invalid()));
+            }
+            heat = Some((min, max, parse_rgb_color(from)?, parse_rgb_color(to)?));
+            continue;
+        }
+        let (left, width) = match (part.strip_prefix("pad("), part.strip_prefix("rpad(")) {
+            (Some(width), _) => (true, width),
+            (_, Some(width)) => (false, width),
+            _ => {
+                parts.push(part);
+                continue;
+            }
+        };
+        let width = width
+            .strip_suffix(')')
+            .and_then(|width| width.trim().parse().ok())
+            .context(format!("invalid padding: \"{}\"", part))?;
+        padding = Some(Padding {
+            width,
+            left,
+            truncate: false,
+        });
+    }
+
+    let style = if parts.is_empty() {
+        Style::new()
+    } else {
+        parse_style(&parts.join(","))?
+    };
+    let style = match (gradient, heat) {
+        (Some(_), Some(_)) => {
+            return Err(anyhow::anyhow!(format!(
+                "bg_gradient and heat can't be used together: \"{}\"",
+                s
+            )))
+        }
+        (Some((from, to)), None) => GroupStyle::BackgroundGradient { style, from, to },
+        (None, Some((min, max, from, to))) => GroupStyle::Heat {
+            style,
+            min,
+            max,
+            from,
+            to,
+        },
+        (None, None) => GroupStyle::Fixed(style),
+    };
+    let style = if first_line_only {
+        GroupStyle::FirstLineOnly(Box::new(style))
+    } else {
+        style
+    };
+    let style = match humanize {
+        Some(humanize) => GroupStyle::Humanized {
+            style: Box::new(style),
+            humanize,
+        },
+        None => style,
+    };
+    Ok(match padding {
+        Some(padding) => GroupStyle::Padded {
+            style: Box::new(style),
+            padding,
+        },
+        None => style,
+    })
+}
+
+fn parse_styles(styles: Vec<String>) -> Result<HashMap<String, GroupStyle>> {
+    let mut map = HashMap::new();
+    for style in styles {
+        let (key, value) = style
+            .split_once('=')
+            .context("invalid styles, format is key=value,key=value")?;
+        let style = parse_group_style(value)?;
+        map.insert(key.to_string(), style);
+    }
+    Ok(map)
+}
+
+/// Adds the key=value style pairs from `input`, one per line, to `styles`,
+/// for --styles-stdin. Blank lines are skipped.
+fn read_styles(input: impl BufRead, styles: &mut Vec<String>) -> Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            styles.push(line.trim().to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Returns the key=value style pairs in the contents of a styles file,
+/// skipping blank lines and comments.
+fn parse_styles_file(contents: &str) -> Result<Vec<String>> {
+    let mut styles = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if !line.contains('=') {
+            return Err(anyhow::anyhow!(format!(
+                "invalid style on line {}: \"{}\", format is key=value",
+                i + 1,
+                line
+            )));
+        }
+        styles.push(line.to_string());
+    }
+    Ok(styles)
+}
+
+/// Returns the regex and the key=value style pairs in the contents of a
+/// --rules file.
+fn parse_rules(contents: &str) -> Result<(String, Vec<String>)> {
+    let mut lines: Vec<&str> = contents.lines().collect();
+    let first = lines
+        .iter()
+        .position(|line| {
+            let line = line.trim();
+            !line.is_empty() && !line.starts_with('#')
+        })
+        .context("there's no regex, it goes on the first line")?;
+    let regex = lines[first].to_string();
+    // Blanking the regex out leaves a styles file, keeping its line numbers.
+    lines[first] = "";
+    Ok((regex, parse_styles_file(&lines.join("\n"))?))
+}
+
+fn parse_timestamp_regex(pattern: &str) -> Result<Regex> {
+    let regex = Regex::new(pattern).context("invalid timestamp pattern")?;
+    for name in ["hour", "minute", "second"] {
+        if !regex.capture_names().any(|n| n == Some(name)) {
+            return Err(anyhow::anyhow!(format!(
+                "timestamp pattern is missing the \"{}\" group",
+                name
+            )));
+        }
+    }
+    Ok(regex)
+}
+
+/// Returns the number of days between 1970-01-01 and the given date in the
+/// proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Finds a timestamp at the start of the line, returning its length in bytes,
+/// the number of seconds it represents and whether it included a date.
+fn parse_timestamp(regex: &Regex, line: &str) -> Option<(usize, f64, bool)> {
+    let captures = regex.captures(line)?;
+    let whole = captures.get(0)?;
+    if whole.start() != 0 {
+        return None;
+    }
+
+    let number = |name: &str| -> Option<i64> { captures.name(name)?.as_str().parse().ok() };
+    let mut seconds = (number("hour")? * 3600 + number("minute")? * 60 + number("second")?) as f64;
+    if let Some(fraction) = captures.name("fraction") {
+        seconds += format!("0.{}", fraction.as_str()).parse::<f64>().ok()?;
+    }
+
+    let date = match (number("year"), number("month"), number("day")) {
+        (Some(year), Some(month), Some(day)) => Some(days_from_civil(year, month, day)),
+        _ => None,
+    };
+    if let Some(days) = date {
+        seconds += (days * 86400) as f64;
+    }
+
+    Some((whole.end(), seconds, date.is_some()))
+}
+
+fn parse_between(between: &[String]) -> Result<Vec<(Regex, Regex, Style)>> {
+    between
+        .chunks(3)
+        .map(|chunk| {
+            let start = Regex::new(&chunk[0]).context("invalid --between start regex")?;
+            let end = Regex::new(&chunk[1]).context("invalid --between end regex")?;
+            Ok((start, end, parse_style(&chunk[2])?))
+        })
+        .collect()
+}
+
+/// A range of columns or lines from `parse_range`.
+type Range = (usize, Option<usize>);
+
+/// Parses a `START:END` range of columns or lines, counting from 1 and
+/// including both ends, into the 0-based index of the first one and the index
+/// just after the last one, if there is a last one.
+fn parse_range(s: &str) -> Result<Range> {
+    let invalid = || format!("invalid range: \"{}\", format is START:END", s);
+    let (start, end) = s.split_once(':').with_context(invalid)?;
+    let start = match start {
+        "" => 1,
+        start => start.parse::<usize>().with_context(invalid)?,
+    };
+    let end = match end {
+        "" => None,
+        end => Some(end.parse::<usize>().with_context(invalid)?),
+    };
+    if start == 0 || end.is_some_and(|end| end < start) {
+        return Err(anyhow::anyhow!(invalid()));
+    }
+    Ok((start - 1, end))
+}
+
+/// Parses `START:END=STYLE` line ranges.
+fn parse_line_ranges(ranges: &[String]) -> Result<Vec<(Range, Style)>> {
+    ranges
+        .iter()
+        .map(|range| {
+            let (lines, style) = range.split_once('=').context(format!(
+                "invalid line range: \"{}\", format is START:END=STYLE",
+                range
+            ))?;
+            Ok((parse_range(lines)?, parse_style(style)?))
+        })
+        .collect()
+}
+
+/// Returns the style of the last of `ranges` that includes the line at
+/// 0-based `index`, if any do.
+fn line_range_style(ranges: &[(Range, Style)], index: usize) -> Option<Style> {
+    ranges
+        .iter()
+        .rev()
+        .find(|((start, end), _)| index >= *start && end.is_none_or(|end| index < end))
+        .map(|(_, style)| *style)
+}
+
+/// Returns the byte range of `line` covered by `columns`, from `parse_range`.
+/// With `tab_stop`, a tab takes up the columns up to the next multiple of it,
+/// and is only covered if all of them are.
+fn column_range<H: Haystack + ?Sized>(
+    line: &H,
+    columns: Range,
+    tab_stop: Option<usize>,
+) -> (usize, usize) {
+    if let Some(tab_stop) = tab_stop {
+        let mut start = None;
+        let mut column = 0;
+        for (i, c) in line.char_indices() {
+            let width = if c == '\t' {
+                tab_stop - column % tab_stop
+            } else {
+                1
+            };
+            if column >= columns.0 && start.is_none() {
+                start = Some(i);
+            }
+            if columns.1.is_some_and(|end| column + width > end) {
+                return (start.unwrap_or(i), i);
+            }
+            column += width;
+        }
+        return (start.unwrap_or(line.len()), line.len());
+    }
+    let byte = |column: usize| {
+        line.char_indices()
+            .nth(column)
+            .map_or(line.len(), |(i, _)| i)
+    };
+    (byte(columns.0), columns.1.map_or(line.len(), byte))
+}
+
+/// Returns the spans of text that sit between a match of `start` and the next
+/// match of `end` after it.
+fn between_spans(start: &Regex, end: &Regex, line: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut position = 0;
+    while let Some(open) = start.find_at(line, position) {
+        let Some(close) = end.find_at(line, open.end()) else {
+            break;
+        };
+        if close.start() > open.end() {
+            spans.push((open.end(), close.start()));
+        }
+        // Make sure we always move forward, even if both markers are empty.
+        position = if close.end() > open.start() {
+            close.end()
+        } else {
+            match line[open.start()..].chars().next() {
+                Some(c) => open.start() + c.len_utf8(),
+                None => break,
+            }
+        };
+    }
+    spans
+}
+
+enum Op {
+    Push(usize),
+    Pop(usize),
+}
+
+struct Span {
+    style: Style,
+    label: String,
+}
+
+/// Style changes to make at each byte position of a line. Every span gets its
+/// own id so that it can be popped off the style stack even when it overlaps
+/// another span instead of nesting inside it.
+#[derive(Default)]
+struct Ops {
+    by_position: HashMap<usize, Vec<Op>>,
+    spans: Vec<Span>,
+    /// Spaces to write at a position, outside of the spans that end or start
+    /// there.
+    padding: HashMap<usize, usize>,
+    /// Bytes to leave out, from one position up to another.
+    cuts: HashMap<usize, usize>,
+    /// Escape sequences from the input to write at a position, with
+    /// --ignore-ansi-in-matching.
+    escapes: HashMap<usize, String>,
+    /// Hyperlinks to write, from one position up to another, and where they
+    /// link to.
+    links: HashMap<usize, (usize, String)>,
+}
+
+impl Ops {
+    /// Adds a span to style. Empty spans have nothing to style, so are
+    /// ignored.
+    fn push_span(&mut self, start: usize, end: usize, style: Style, label: impl Into<String>) {
+        if start >= end {
+            return;
+        }
+        let id = self.spans.len();
+        self.spans.push(Span {
+            style,
+            label: label.into(),
+        });
+        self.by_position
+            .entry(start)
+            .or_default()
+            .push(Op::Push(id));
+        self.by_position.entry(end).or_default().push(Op::Pop(id));
+    }
+
+    fn push_padding(&mut self, position: usize, spaces: usize) {
+        *self.padding.entry(position).or_default() += spaces;
+    }
+
+    fn push_cut(&mut self, start: usize, end: usize) {
+        let cut = self.cuts.entry(start).or_default();
+        *cut = (*cut).max(end);
+    }
+
+    fn push_escape(&mut self, position: usize, escape: String) {
+        self.escapes.entry(position).or_default().push_str(&escape);
+    }
+
+    /// Adds a hyperlink to `target` around the text from `start` to `end`.
+    /// Empty links have nothing to click on, so are ignored.
+    fn push_link(&mut self, start: usize, end: usize, target: &str) {
+        if start < end {
+            self.links.insert(start, (end, target.to_string()));
+        }
+    }
+
+    /// Returns the spans, padding and cuts between `start` and `end`, moved
+    /// so that `start` is at position 0. Spans only partly inside are cut
+    /// down to the part that is.
+    fn clip(&self, start: usize, end: usize) -> Ops {
+        let mut clipped = Ops::default();
+        for (span, (from, to)) in self.spans.iter().zip(self.ranges()) {
+            clipped.push_span(
+                from.max(start) - start,
+                to.min(end).saturating_sub(start),
+                span.style,
+                span.label.as_str(),
+            );
+        }
+        let inside = |position: &usize| (start..=end).contains(position);
+        for (position, spaces) in self.padding.iter().filter(|(p, _)| inside(p)) {
+            clipped.push_padding(position - start, *spaces);
+        }
+        for (position, cut) in self.cuts.iter().filter(|(p, _)| inside(p)) {
+            clipped.push_cut(position - start, cut.min(&end) - start);
+        }
+        for (position, escape) in self.escapes.iter().filter(|(p, _)| inside(p)) {
+            clipped.push_escape(position - start, escape.clone());
+        }
+        for (from, (to, target)) in &self.links {
+            clipped.push_link(
+                from.max(&start) - start,
+                to.min(&end).saturating_sub(start),
+                target,
+            );
+        }
+        clipped
+    }
+
+    /// Returns where each span starts and ends, by id.
+    fn ranges(&self) -> Vec<(usize, usize)> {
+        let mut ranges = vec![(0, 0); self.spans.len()];
+        for (position, ops) in &self.by_position {
+            for op in ops {
+                match op {
+                    Op::Push(id) => ranges[*id].0 = *position,
+                    Op::Pop(id) => ranges[*id].1 = *position,
+                }
+            }
+        }
+        ranges
+    }
+
+    /// Returns the spans, padding, cuts and links with any position that's
+    /// inside a grapheme of `line` moved to the end of it, for
+    /// --whole-graphemes.
+    fn snap_to_graphemes(&self, line: &str) -> Ops {
+        let snap = |position: usize| grapheme_end(line, position);
+        let mut snapped = Ops::default();
+        for (span, (from, to)) in self.spans.iter().zip(self.ranges()) {
+            snapped.push_span(snap(from), snap(to), span.style, span.label.as_str());
+        }
+        for (position, spaces) in &self.padding {
+            snapped.push_padding(snap(*position), *spaces);
+        }
+        for (position, cut) in &self.cuts {
+            snapped.push_cut(snap(*position), snap(*cut));
+        }
+        for (from, (to, target)) in &self.links {
+            snapped.push_link(snap(*from), snap(*to), target);
+        }
+        snapped.escapes = self.escapes.clone();
+        snapped
+    }
+
+    fn get(&self, position: usize) -> &[Op] {
+        self.by_position
+            .get(&position)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+/// Returns `position` if it's between two graphemes of `line`, or the end of
+/// the grapheme it's inside otherwise.
+fn grapheme_end(line: &str, position: usize) -> usize {
+    line.grapheme_indices(true)
+        .map(|(start, grapheme)| (start, start + grapheme.len()))
+        .find(|(_, end)| *end > position)
+        .map_or(
+            position,
+            |(start, end)| {
+                if start == position {
+                    start
+                } else {
+                    end
+                }
+            },
+        )
+}
+
+/// A style for text: an owo-colors style, along with the attributes that
+/// owo-colors doesn't have.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Style {
+    owo: owo_colors::Style,
+    /// SGR 51, which draws a frame around the text.
+    framed: bool,
+    /// SGR 52, which draws a circle around the text.
+    encircled: bool,
+}
+
+impl Style {
+    fn new() -> Self {
+        Style::default()
+    }
+
+    /// Returns the style with `f` applied to its owo-colors style.
+    fn map(self, f: impl FnOnce(owo_colors::Style) -> owo_colors::Style) -> Self {
+        Style {
+            owo: f(self.owo),
+            ..self
+        }
+    }
+
+    fn is_plain(&self) -> bool {
+        self.owo.is_plain() && !self.framed && !self.encircled
+    }
+}
+
+impl From<owo_colors::Style> for Style {
+    fn from(owo: owo_colors::Style) -> Self {
+        Style {
+            owo,
+            ..Default::default()
+        }
+    }
+}
+
+/// Writes the ANSI escape sequence that turns on a style, without the text
+/// and reset that `OwoColorize::style` would wrap around it.
+struct Prefix(Style);
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.owo.fmt_prefix(f)?;
+        if self.0.framed {
+            f.write_str("\x1b[51m")?;
+        }
+        if self.0.encircled {
+            f.write_str("\x1b[52m")?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the ANSI escape sequence that turns a style back off.
+struct Suffix(Style);
+
+impl fmt::Display for Suffix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_plain() {
+            return Ok(());
+        }
+        f.write_str("\x1b[0m")
+    }
+}
+
+/// The lightest and darkest of the 256-color palette's greys used by --fade.
+const FADE_LIGHTEST: u8 = 252;
+const FADE_DARKEST: u8 = 238;
+
+/// Returns the grey that --fade draws a line in, given how many lines it is
+/// after the last line with a match, if there's been one, and how many lines
+/// it takes to reach the darkest grey.
+fn fade_style(since_match: Option<usize>, steps: usize) -> Option<Style> {
+    let distance = since_match.unwrap_or(steps).min(steps);
+    if distance == 0 {
+        return None;
+    }
+    let range = (FADE_LIGHTEST - FADE_DARKEST) as usize;
+    let shade = FADE_LIGHTEST - (range * distance / steps) as u8;
+    Some(Style::new().map(|s| s.color(DynColors::Xterm(shade.into()))))
+}
+
+/// The text to write around matches with --delimit-matches.
+struct Delimiters {
+    default: (String, String),
+    /// Delimiters for some groups instead, by the groups' labels.
+    by_group: HashMap<String, (String, String)>,
+}
+
+impl Delimiters {
+    fn new(
+        default: &str,
+        group_delimiters: &[String],
+        groups: &[(String, Option<&GroupStyle>)],
+    ) -> Result<Self> {
+        let parse = |s: &str| {
+            s.split_once(',')
+                .map(|(open, close)| (open.to_string(), close.to_string()))
+                .context(format!("invalid delimiters {}, format is OPEN,CLOSE", s))
+        };
+        let mut by_group = HashMap::new();
+        for entry in group_delimiters {
+            let (group, delimiters) = entry
+                .split_once('=')
+                .context("invalid --group-delimiters, format is GROUP=OPEN,CLOSE")?;
+            let (label, _) = groups
+                .iter()
+                .enumerate()
+                .skip(1)
+                .find(|(i, (label, _))| label == group || i.to_string() == group)
+                .map(|(_, group)| group)
+                .context(format!(
+                    "there are --group-delimiters for \"{}\" but no group has that name",
+                    group
+                ))?;
+            by_group.insert(label.clone(), parse(delimiters)?);
+        }
+        Ok(Delimiters {
+            default: parse(default)?,
+            by_group,
+        })
+    }
+
+    /// Returns the opening and closing delimiters for spans with `label`.
+    fn get(&self, label: &str) -> (&str, &str) {
+        let (open, close) = self.by_group.get(label).unwrap_or(&self.default);
+        (open, close)
+    }
+}
+
+/// The first strong isolate and pop directional isolate characters, which
+/// --bidi-isolate writes around matches.
+const FIRST_STRONG_ISOLATE: &str = "\u{2068}";
+const POP_DIRECTIONAL_ISOLATE: &str = "\u{2069}";
+
+/// Returns the delimiters to write around matches, if any, from
+/// --bidi-isolate or --delimit-matches and --group-delimiters.
+fn delimiters(
+    bidi_isolate: bool,
+    delimit_matches: Option<&str>,
+    group_delimiters: &[String],
+    groups: &[(String, Option<&GroupStyle>)],
+) -> Result<Option<Delimiters>> {
+    if bidi_isolate {
+        return Ok(Some(Delimiters {
+            default: (
+                FIRST_STRONG_ISOLATE.to_string(),
+                POP_DIRECTIONAL_ISOLATE.to_string(),
+            ),
+            by_group: HashMap::new(),
+        }));
+    }
+    delimit_matches
+        .map(|default| Delimiters::new(default, group_delimiters, groups))
+        .transpose()
+}
+
+/// Options for how `write_styled` turns spans into output.
+#[derive(Clone, Copy, Default)]
+struct Render<'a> {
+    /// Wrap spans in `«label:...»` markers instead of styling them.
+    dry_run: bool,
+    /// Write text without any styling.
+    no_color: bool,
+    /// A style for the whole line, which span styles are layered on top of.
+    base: Style,
+    /// Which styles to use where spans overlap.
+    mode: StyleMode,
+    /// Replace spaces and tabs in lines with visible glyphs.
+    show_whitespace: bool,
+    /// A byte of the line, and a base style to use instead from there on.
+    overflow: Option<(usize, Style)>,
+    /// Write text that's next to text with the same styles along with it,
+    /// instead of on its own.
+    merge_adjacent: bool,
+    /// Text to write around matches, whether or not they're styled.
+    delimiters: Option<&'a Delimiters>,
+}
+
+impl Render<'_> {
+    /// Returns the delimiters to write around the span `id`, if it's a
+    /// match and there are any.
+    fn delimiters<'d>(&'d self, ops: &'d Ops, id: usize) -> Option<(&'d str, &'d str)> {
+        let label = &ops.spans[id].label;
+        self.delimiters
+            .filter(|_| !label.is_empty())
+            .map(|delimiters| delimiters.get(label))
+    }
+
+    fn write_text(&self, output: &mut impl Write, text: &str, style: Style) -> Result<()> {
+        self.write_bytes(output, text.as_bytes(), style)
+    }
+
+    /// Writes an escape sequence that turns off all styles, unless the output
+    /// isn't styled at all.
+    fn write_reset(&self, output: &mut impl Write) -> Result<()> {
+        if !self.dry_run && !self.no_color {
+            write!(output, "\x1b[0m")?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether writing text with `styles` ends with a reset, which
+    /// turns off any styles from escape sequences in the input too.
+    fn resets(&self, styles: &[Style]) -> bool {
+        !self.dry_run
+            && !self.no_color
+            && (self.show_whitespace
+                || std::iter::once(&self.base)
+                    .chain(styles)
+                    .any(|style| !style.is_plain()))
+    }
+
+    /// Writes the OSC 8 sequence that starts a hyperlink to `target`, or ends
+    /// one if `target` is empty.
+    fn write_link(&self, output: &mut impl Write, target: &str) -> Result<()> {
+        if !self.dry_run && !self.no_color {
+            write!(output, "\x1b]8;;{}\x1b\\", target)?;
+        }
+        Ok(())
+    }
+
+    fn write_bytes(&self, output: &mut impl Write, text: &[u8], style: Style) -> Result<()> {
+        self.write_layers(output, text, &[style])
+    }
+
+    /// Writes part of a line with `write_layers`, showing whitespace if
+    /// that's been asked for.
+    fn write_line_text(
+        &self,
+        output: &mut impl Write,
+        text: &[u8],
+        styles: &[Style],
+    ) -> Result<()> {
+        if !self.show_whitespace {
+            return self.write_layers(output, text, styles);
+        }
+
+        let is_whitespace = |b: &u8| *b == b' ' || *b == b'\t';
+        let mut whitespace_styles = styles.to_vec();
+        whitespace_styles.push(Style::new().map(|s| s.dimmed()));
+        let mut rest = text;
+        while !rest.is_empty() {
+            let len = rest.iter().take_while(|b| !is_whitespace(b)).count();
+            self.write_layers(output, &rest[..len], styles)?;
+            rest = &rest[len..];
+
+            let len = rest.iter().take_while(|b| is_whitespace(b)).count();
+            let glyphs: String = rest[..len]
+                .iter()
+                .map(|b| if *b == b' ' { '·' } else { '→' })
+                .collect();
+            self.write_layers(output, glyphs.as_bytes(), &whitespace_styles)?;
+            rest = &rest[len..];
+        }
+        Ok(())
+    }
+
+    /// Writes `text` with each of `styles` layered on top of the base style.
+    fn write_layers(&self, output: &mut impl Write, text: &[u8], styles: &[Style]) -> Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        if self.dry_run || self.no_color {
+            output.write_all(text)?;
+            return Ok(());
+        }
+
+        write!(output, "{}", Prefix(self.base))?;
+        for style in styles {
+            write!(output, "{}", Prefix(*style))?;
+        }
+        output.write_all(text)?;
+        // A single reset at the end turns off every layer, base included.
+        let reset = std::iter::once(&self.base)
+            .chain(styles)
+            .find(|style| !style.is_plain());
+        if let Some(style) = reset {
+            write!(output, "{}", Suffix(*style))?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds the SGR sequences in `escape` to `styles`, starting again after any
+/// that reset them.
+fn track_input_styles(styles: &mut String, escape: &str) {
+    for sequence in escape.split('\x1b').filter(|s| !s.is_empty()) {
+        let Some(params) = sequence.strip_prefix('[').and_then(|s| s.strip_suffix('m')) else {
+            continue;
+        };
+        if params.is_empty() || params == "0" || params.starts_with("0;") {
+            styles.clear();
+        }
+        if !params.is_empty() && params != "0" {
+            styles.push('\x1b');
+            styles.push_str(sequence);
+        }
+    }
+}
+
+/// Text of a line waiting to be written by `write_styled`, and its styles.
+type PendingText = Option<(std::ops::Range<usize>, Vec<Style>)>;
+
+/// Adds the text from `range` of a line to what's waiting to be written,
+/// writing what was waiting first unless the text can go along with it.
+fn push_text(
+    output: &mut impl Write,
+    line: &[u8],
+    render: &Render,
+    pending: &mut PendingText,
+    (range, styles): (std::ops::Range<usize>, Vec<Style>),
+    input_styles: &str,
+) -> Result<()> {
+    match pending {
+        Some((waiting, waiting_styles))
+            if render.merge_adjacent && waiting.end == range.start && *waiting_styles == styles =>
+        {
+            waiting.end = range.end;
+        }
+        _ => {
+            write_pending(output, line, render, pending, input_styles)?;
+            *pending = Some((range, styles));
+        }
+    }
+    if !render.merge_adjacent {
+        write_pending(output, line, render, pending, input_styles)?;
+    }
+    Ok(())
+}
+
+/// Writes the text waiting to be written, if there is any.
+fn write_pending(
+    output: &mut impl Write,
+    line: &[u8],
+    render: &Render,
+    pending: &mut PendingText,
+    input_styles: &str,
+) -> Result<()> {
+    if let Some((range, styles)) = pending.take() {
+        render.write_line_text(output, &line[range], &styles)?;
+        if render.resets(&styles) {
+            output.write_all(input_styles.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes `line` with the styles from `ops` applied.
+fn write_styled(output: &mut impl Write, line: &[u8], ops: &Ops, mut render: Render) -> Result<()> {
+    let mut style_stack: Vec<usize> = Vec::new();
+    let mut last = 0;
+    let mut link_end = None;
+    let mut pending = None;
+    // The SGR sequences from the input since it last reset its styles, to
+    // turn back on after a reset of our own.
+    let mut input_styles = String::new();
+    let styles = |stack: &[usize]| -> Vec<Style> {
+        let ids = match render.mode {
+            StyleMode::Innermost => &stack[stack.len().saturating_sub(1)..],
+            StyleMode::Outermost => &stack[..stack.len().min(1)],
+            StyleMode::Stack => stack,
+        };
+        ids.iter().map(|id| ops.spans[*id].style).collect()
+    };
+
+    for position in 0..=line.len() {
+        let ops_here = ops.get(position);
+        let padding = ops.padding.get(&position);
+        let cut = ops.cuts.get(&position);
+        let overflow = render.overflow.filter(|(at, _)| *at == position);
+        let link = ops.links.get(&position);
+        let escape = ops.escapes.get(&position);
+        if ops_here.is_empty()
+            && padding.is_none()
+            && cut.is_none()
+            && overflow.is_none()
+            && link.is_none()
+            && link_end != Some(position)
+            && escape.is_none()
+        {
+            continue;
+        }
+
+        // Nothing is written inside a cut, but spans still start and stop.
+        if last < position {
+            let text = (last..position, styles(&style_stack));
+            push_text(output, line, &render, &mut pending, text, &input_styles)?;
+            last = position;
+        }
+        // Anything else written here has to come after the text so far.
+        let writes = render.dry_run
+            || render.delimiters.is_some()
+            || overflow.is_some()
+            || padding.is_some()
+            || escape.is_some()
+            || link.is_some()
+            || link_end == Some(position);
+        if writes {
+            write_pending(output, line, &render, &mut pending, &input_styles)?;
+        }
+        if let Some((_, style)) = overflow {
+            render.base = style;
+        }
+
+        // Close spans before opening new ones, so that dry-run markers for
+        // adjacent spans don't end up inside each other.
+        for op in ops_here {
+            if let Op::Pop(id) = op {
+                if let Some(i) = style_stack.iter().rposition(|s| s == id) {
+                    style_stack.remove(i);
+                    if render.dry_run && !ops.spans[*id].label.is_empty() {
+                        write!(output, "»")?;
+                    }
+                    if let Some((_, close)) = render.delimiters(ops, *id) {
+                        output.write_all(close.as_bytes())?;
+                    }
+                }
+            }
+        }
+        if let Some(spaces) = padding {
+            let spaces = " ".repeat(*spaces);
+            let styles = styles(&style_stack);
+            render.write_layers(output, spaces.as_bytes(), &styles)?;
+            if render.resets(&styles) {
+                output.write_all(input_styles.as_bytes())?;
+            }
+        }
+        if let Some(escape) = escape {
+            output.write_all(escape.as_bytes())?;
+            track_input_styles(&mut input_styles, escape);
+        }
+        for op in ops_here {
+            if let Op::Push(id) = op {
+                style_stack.push(*id);
+                if render.dry_run && !ops.spans[*id].label.is_empty() {
+                    write!(output, "«{}:", ops.spans[*id].label)?;
+                }
+                if let Some((open, _)) = render.delimiters(ops, *id) {
+                    output.write_all(open.as_bytes())?;
+                }
+            }
+        }
+        if link_end == Some(position) {
+            render.write_link(output, "")?;
+            link_end = None;
+        }
+        if let Some((end, target)) = link.filter(|_| link_end.is_none()) {
+            render.write_link(output, target)?;
+            link_end = Some(*end);
+        }
+        if let Some(end) = cut {
+            last = last.max(*end);
+        }
+    }
+
+    let text = (last..line.len(), styles(&style_stack));
+    push_text(output, line, &render, &mut pending, text, &input_styles)?;
+    write_pending(output, line, &render, &mut pending, &input_styles)?;
+    if link_end.is_some() {
+        render.write_link(output, "")?;
+    }
+    Ok(())
+}
+
+/// Writes the parts of `line` in `ranges` with the styles from `ops`, with
+/// `placeholder` in place of each run of text outside them.
+fn write_collapsed(
+    output: &mut impl Write,
+    line: &[u8],
+    ops: &Ops,
+    render: Render,
+    ranges: &[std::ops::Range<usize>],
+    placeholder: &str,
+) -> Result<()> {
+    let mut last = 0;
+    for range in ranges.iter().filter(|range| !range.is_empty()) {
+        if last < range.start {
+            render.write_text(output, placeholder, Style::new())?;
+        }
+        let ops = ops.clip(range.start, range.end);
+        write_styled(output, &line[range.clone()], &ops, render)?;
+        last = range.end;
+    }
+    if last < line.len() {
+        render.write_text(output, placeholder, Style::new())?;
+    }
+    Ok(())
+}
+
+/// The regex used by `--urls`. A URL can't end in punctuation, so that one at
+/// the end of a sentence or in brackets stops before it.
+const URL_REGEX: &str = r#"\b(?P<url>(?:https?|ftp|file)://[^\s<>"'`]*[^\s<>"'`.,;:!?)\]}])"#;
+
+/// Builds a regex matching any of the given words, along with styles for the
+/// group each word is captured by and the word to label each group with.
+/// Words only have to have a word boundary on a side that starts or ends with
+/// a word character, so that ones like `c++` still match.
+fn words_regex(words: &[String]) -> Result<(String, Vec<String>, HashMap<String, String>)> {
+    let boundary = |c: Option<char>| match c {
+        Some(c) if c.is_alphanumeric() || c == '_' => r"\b",
+        _ => "",
+    };
+    let mut alternatives = Vec::new();
+    let mut styles = Vec::new();
+    let mut labels = HashMap::new();
+    for (i, word) in words.iter().enumerate() {
+        let (word, style) = word
+            .split_once('=')
+            .context("invalid word, format is word=style")?;
+        let name = format!("word{}", i);
+        alternatives.push(format!(
+            "{}(?P<{}>{}){}",
+            boundary(word.chars().next()),
+            name,
+            regex::escape(word),
+            boundary(word.chars().last()),
+        ));
+        styles.push(format!("{}={}", name, style));
+        labels.insert(name, word.to_string());
+    }
+    Ok((alternatives.join("|"), styles, labels))
+}
+
+fn build_regex(args: &Args) -> Result<Regex> {
+    RegexBuilder::new(args.regex.as_deref().context("no regex given")?)
+        .multi_line(args.multi_line_anchors)
+        .ignore_whitespace(args.extended)
+        .case_insensitive(args.ignore_case)
+        .build()
+        .context("invalid regex")
+}
+
+fn build_bytes_regex(args: &Args) -> Result<regex::bytes::Regex> {
+    regex::bytes::RegexBuilder::new(args.regex.as_deref().context("no regex given")?)
+        .multi_line(args.multi_line_anchors)
+        .ignore_whitespace(args.extended)
+        .case_insensitive(args.ignore_case)
+        .build()
+        .context("invalid regex")
+}
+
+/// What `run` matches the regex against: text, or with --bytes, raw bytes. In
+/// raw bytes, each byte counts as a character. Options that need to
+/// understand the text, like --replace, can't be used with --bytes, so only
+/// ever see lines that `to_str` works on.
+trait Haystack:
+    ToOwned<Owned = Self::Line> + std::ops::Index<std::ops::Range<usize>, Output = Self>
+{
+    /// An owned line, `String` or `Vec<u8>`.
+    type Line: std::ops::Deref<Target = Self> + From<String> + Clone + Eq + std::hash::Hash;
+    type Regex;
+
+    fn build_regex(args: &Args) -> Result<Self::Regex>;
+    fn new_regex(pattern: &str) -> Result<Self::Regex, regex::Error>;
+    fn capture_names(regex: &Self::Regex) -> Vec<Option<&str>>;
+    fn is_match(regex: &Self::Regex, haystack: &Self) -> bool;
+    fn captures<'a>(
+        regex: &'a Self::Regex,
+        haystack: &'a Self,
+    ) -> impl Iterator<Item = Captures> + 'a;
+
+    /// Turns a line read from the input into a `Line`, or an `InvalidData`
+    /// error if it has to be text and isn't.
+    fn from_bytes(bytes: Vec<u8>) -> io::Result<Self::Line>;
+    fn from_text(text: &str) -> &Self;
+    fn as_bytes(&self) -> &[u8];
+    fn to_str(&self) -> Option<&str>;
+    fn char_indices(&self) -> impl Iterator<Item = (usize, char)> + '_;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool;
+    fn trim(&self) -> &Self;
+
+    /// Joins `lines` together with newlines between them, for --window.
+    fn join_lines(lines: &[&Self]) -> Self::Line;
+    fn push(line: &mut Self::Line, text: &Self);
+    fn truncate(line: &mut Self::Line, len: usize);
+    fn replace_range(line: &mut Self::Line, range: std::ops::Range<usize>, text: &Self);
+}
+
+impl Haystack for str {
+    type Line = String;
+    type Regex = Regex;
+
+    fn build_regex(args: &Args) -> Result<Regex> {
+        build_regex(args)
+    }
+
+    fn new_regex(pattern: &str) -> Result<Regex, regex::Error> {
+        Regex::new(pattern)
+    }
+
+    fn capture_names(regex: &Regex) -> Vec<Option<&str>> {
+        regex.capture_names().collect()
+    }
+
+    fn is_match(regex: &Regex, haystack: &str) -> bool {
+        regex.is_match(haystack)
+    }
+
+    fn captures<'a>(regex: &'a Regex, haystack: &'a str) -> impl Iterator<Item = Captures> + 'a {
+        regex
+            .captures_iter(haystack)
+            .map(|m| Captures(m.iter().map(|c| c.map(|c| c.range())).collect()))
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> io::Result<String> {
+        String::from_utf8(bytes).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stream did not contain valid UTF-8",
+            )
+        })
+    }
+
+    fn from_text(text: &str) -> &str {
+        text
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        str::as_bytes(self)
+    }
+
+    fn to_str(&self) -> Option<&str> {
+        Some(self)
+    }
+
+    fn char_indices(&self) -> impl Iterator<Item = (usize, char)> + '_ {
+        str::char_indices(self)
+    }
+
+    fn len(&self) -> usize {
+        str::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        str::is_empty(self)
+    }
+
+    fn trim(&self) -> &str {
+        str::trim(self)
+    }
+
+    fn join_lines(lines: &[&str]) -> String {
+        lines.join("\n")
+    }
+
+    fn push(line: &mut String, text: &str) {
+        line.push_str(text);
+    }
+
+    fn truncate(line: &mut String, len: usize) {
+        line.truncate(len);
+    }
+
+    fn replace_range(line: &mut String, range: std::ops::Range<usize>, text: &str) {
+        line.replace_range(range, text);
+    }
+}
+
+impl Haystack for [u8] {
+    type Line = Vec<u8>;
+    type Regex = regex::bytes::Regex;
+
+    fn build_regex(args: &Args) -> Result<regex::bytes::Regex> {
+        build_bytes_regex(args)
+    }
+
+    fn new_regex(pattern: &str) -> Result<regex::bytes::Regex, regex::Error> {
+        regex::bytes::Regex::new(pattern)
+    }
+
+    fn capture_names(regex: &regex::bytes::Regex) -> Vec<Option<&str>> {
+        regex.capture_names().collect()
+    }
+
+    fn is_match(regex: &regex::bytes::Regex, haystack: &[u8]) -> bool {
+        regex.is_match(haystack)
+    }
+
+    fn captures<'a>(
+        regex: &'a regex::bytes::Regex,
+        haystack: &'a [u8],
+    ) -> impl Iterator<Item = Captures> + 'a {
+        regex
+            .captures_iter(haystack)
+            .map(|m| Captures(m.iter().map(|c| c.map(|c| c.range())).collect()))
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+        Ok(bytes)
+    }
+
+    fn from_text(text: &str) -> &[u8] {
+        text.as_bytes()
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        self
+    }
+
+    fn to_str(&self) -> Option<&str> {
+        std::str::from_utf8(self).ok()
+    }
+
+    fn char_indices(&self) -> impl Iterator<Item = (usize, char)> + '_ {
+        self.iter().enumerate().map(|(i, b)| (i, *b as char))
+    }
+
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        <[u8]>::is_empty(self)
+    }
+
+    fn trim(&self) -> &[u8] {
+        self.trim_ascii()
+    }
+
+    fn join_lines(lines: &[&[u8]]) -> Vec<u8> {
+        lines.join(&b'\n')
+    }
+
+    fn push(line: &mut Vec<u8>, text: &[u8]) {
+        line.extend_from_slice(text);
+    }
+
+    fn truncate(line: &mut Vec<u8>, len: usize) {
+        line.truncate(len);
+    }
+
+    fn replace_range(line: &mut Vec<u8>, range: std::ops::Range<usize>, text: &[u8]) {
+        line.splice(range, text.iter().copied());
+    }
+}
+
+/// Where each group of a match is in the haystack, by group number.
+struct Captures(Vec<Option<std::ops::Range<usize>>>);
+
+impl Captures {
+    fn get(&self, i: usize) -> Option<std::ops::Range<usize>> {
+        self.0.get(i).cloned().flatten()
+    }
+
+    /// Returns where the whole match is.
+    fn whole(&self) -> std::ops::Range<usize> {
+        self.get(0).unwrap()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Option<std::ops::Range<usize>>> + '_ {
+        self.0.iter().cloned()
+    }
+}
+
+/// Reads `input` a line at a time without line endings, like
+/// `BufRead::lines`, but as any kind of haystack.
+fn read_lines<H: Haystack + ?Sized>(
+    mut input: impl BufRead,
+) -> impl Iterator<Item = io::Result<H::Line>> {
+    std::iter::from_fn(move || {
+        let mut line = Vec::new();
+        match input.read_until(b'\n', &mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                if line.ends_with(b"\n") {
+                    line.pop();
+                    if line.ends_with(b"\r") {
+                        line.pop();
+                    }
+                }
+                Some(H::from_bytes(line))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    })
+}
+
+/// Returns the label and configured style, if any, of each capture group in a
+/// regex with the given capture names, indexed by group number. Groups are
+/// labelled by name if they have one, or their number if not, and can be
+/// styled by either. Groups in `labels` are labelled with that instead.
+fn groups<'a>(
+    names: Vec<Option<&str>>,
+    styles: &'a HashMap<String, GroupStyle>,
+    labels: &HashMap<String, String>,
+) -> Result<Vec<(String, Option<&'a GroupStyle>)>> {
+    for key in styles.keys() {
+        if let Ok(i) = key.parse::<usize>() {
+            if i == 0 || i >= names.len() {
+                return Err(anyhow::anyhow!(format!(
+                    "there is a style for group {} but the regex only has {} groups",
+                    i,
+                    names.len() - 1
+                )));
+            }
+        }
+    }
+
+    for style in styles.values() {
+        if let Some(source) = style.source() {
+            let exists = names.contains(&Some(source))
+                || source
+                    .parse::<usize>()
+                    .is_ok_and(|i| i > 0 && i < names.len());
+            if !exists {
+                return Err(anyhow::anyhow!(format!(
+                    "match@{} refers to a group the regex doesn't have",
+                    source
+                )));
+            }
+        }
+    }
+
+    Ok(names
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let index = i.to_string();
+            let style = name
+                .and_then(|name| styles.get(name))
+                .or_else(|| styles.get(&index));
+            let label = name.map(str::to_string).unwrap_or(index);
+            (labels.get(&label).cloned().unwrap_or(label), style)
+        })
+        .collect())
+}
+
+/// Part of a `--replace` template.
+#[derive(Debug, PartialEq)]
+enum TemplatePart {
+    Literal(String),
+    /// The text of the group with this index.
+    Group(usize),
+    /// The text of the group with this index, with a style from the template
+    /// instead of its own.
+    StyledGroup(usize, GroupStyle),
+    /// Text from the template, with a style that can depend on what groups
+    /// matched.
+    StyledLiteral(String, GroupStyle),
+}
+
+/// Replaces `\t`, `\n`, `\0` and `\\` in `s` with the characters they stand
+/// for.
+fn unescape(s: &str) -> Result<String> {
+    let mut unescaped = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        unescaped.push(match chars.next() {
+            Some('t') => '\t',
+            Some('n') => '\n',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some(c) => return Err(anyhow::anyhow!(format!("unknown escape: \"\\{}\"", c))),
+            None => return Err(anyhow::anyhow!("\\ at the end, use \\\\ for a backslash")),
+        });
+    }
+    Ok(unescaped)
+}
+
+/// Parses a `--replace` template for a regex with the given capture names.
+/// Its styles can't use any of the `disallowed` attributes.
+fn parse_template(
+    template: &str,
+    names: &[Option<&str>],
+    disallowed: &[UnsafeAttribute],
+) -> Result<Vec<TemplatePart>> {
+    let invalid = |reason: &str| {
+        anyhow::anyhow!(format!(
+            "invalid replacement template: \"{}\", {}",
+            template, reason
+        ))
+    };
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut rest = template;
+    while let Some(dollar) = rest.find('$') {
+        literal.push_str(&rest[..dollar]);
+        rest = &rest[dollar + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            literal.push('$');
+            rest = after;
+            continue;
+        }
+
+        if let Some(after) = rest.strip_prefix('[') {
+            let (style, after) = after
+                .split_once("]{")
+                .ok_or_else(|| invalid("a $[STYLE] needs {TEXT} after it"))?;
+            let (text, after) = after
+                .split_once('}')
+                .ok_or_else(|| invalid("a $[STYLE]{ is never closed"))?;
+            check_safe_style(style, disallowed)?;
+            let style = parse_template_style(style, names).map_err(|e| invalid(&e.to_string()))?;
+            if !literal.is_empty() {
+                parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(TemplatePart::StyledLiteral(text.to_string(), style));
+            rest = after;
+            continue;
+        }
+
+        let reference = if let Some(after) = rest.strip_prefix('{') {
+            let end = after
+                .find('}')
+                .ok_or_else(|| invalid("a ${ is never closed"))?;
+            rest = &after[end + 1..];
+            &after[..end]
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let reference = &rest[..end];
+            rest = &rest[end..];
+            reference
+        };
+        if reference.is_empty() {
+            return Err(invalid("use $$ for a literal $"));
+        }
+        // ${group:STYLE} gives the group a style of its own here.
+        let (reference, style) = match reference.split_once(':') {
+            Some((reference, style)) => {
+                check_safe_style(style, disallowed)?;
+                let style =
+                    parse_template_style(style, names).map_err(|e| invalid(&e.to_string()))?;
+                (reference, Some(style))
+            }
+            None => (reference, None),
+        };
+
+        let index = match reference.parse::<usize>() {
+            Ok(i) if i < names.len() => i,
+            Ok(_) => return Err(invalid(&format!("there is no group {}", reference))),
+            Err(_) => names
+                .iter()
+                .position(|name| *name == Some(reference))
+                .ok_or_else(|| invalid(&format!("there is no group named {}", reference)))?,
+        };
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+        }
+        parts.push(match style {
+            Some(style) => TemplatePart::StyledGroup(index, style),
+            None => TemplatePart::Group(index),
+        });
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        parts.push(TemplatePart::Literal(literal));
+    }
+    Ok(parts)
+}
+
+/// Parses a style given in a `--replace` template, checking that any group it
+/// takes its text from is in the regex.
+fn parse_template_style(s: &str, names: &[Option<&str>]) -> Result<GroupStyle> {
+    let style = parse_group_style(s)?;
+    if let Some(source) = style.source() {
+        let exists = match source.parse::<usize>() {
+            Ok(i) => i < names.len(),
+            Err(_) => names.contains(&Some(source)),
+        };
+        if !exists {
+            return Err(anyhow::anyhow!(format!(
+                "match@{} refers to a group the regex doesn't have",
+                source
+            )));
+        }
+    }
+    Ok(style)
+}
+
+/// Returns the text that a `match` style for a group compares against: the
+/// text of its source group if it has one, or the group's own text.
+fn match_key<'h, H: Haystack + ?Sized>(
+    haystack: &'h H,
+    m: &Captures,
+    names: &[Option<&str>],
+    style: Option<&GroupStyle>,
+    text: &'h str,
+) -> Option<&'h str> {
+    match style.and_then(GroupStyle::source) {
+        Some(source) => source
+            .parse::<usize>()
+            .ok()
+            .or_else(|| names.iter().position(|name| *name == Some(source)))
+            .and_then(|i| m.get(i))
+            .and_then(|range| haystack[range].to_str()),
+        None => Some(text),
+    }
+}
+
+/// A match with the text of its groups that have a `humanize` style replaced.
+struct Rewrite {
+    /// The byte of the line the match starts at.
+    start: usize,
+    text: String,
+    /// The parts of the line that were replaced, and how long what replaced
+    /// them is.
+    edits: Vec<(std::ops::Range<usize>, usize)>,
+}
+
+impl Rewrite {
+    fn new(line: &str, m: &Captures, groups: &[(String, Option<&GroupStyle>)]) -> Self {
+        let whole = m.whole();
+        let mut text = String::new();
+        let mut edits = Vec::new();
+        let mut up_to = whole.start;
+        for (i, capture) in m.iter().enumerate().skip(1) {
+            let Some(mat) = capture else {
+                continue;
+            };
+            // Groups inside one that's been replaced go along with it.
+            if mat.start < up_to {
+                continue;
+            }
+            let Some(humanized) = groups[i]
+                .1
+                .and_then(GroupStyle::humanize)
+                .and_then(|humanize| humanize.apply(&line[mat.clone()]))
+            else {
+                continue;
+            };
+            text.push_str(&line[up_to..mat.start]);
+            text.push_str(&humanized);
+            up_to = mat.end;
+            edits.push((mat, humanized.len()));
+        }
+        text.push_str(&line[up_to..whole.end]);
+        Rewrite {
+            start: whole.start,
+            text,
+            edits,
+        }
+    }
+
+    /// Returns where the text from `range` of the line is in the rewritten
+    /// match. Text inside a part that was replaced, like a group inside a
+    /// humanized one, becomes all of what replaced it.
+    fn moved_range(&self, range: &std::ops::Range<usize>) -> Option<std::ops::Range<usize>> {
+        let range = self
+            .edits
+            .iter()
+            .map(|(edit, _)| edit)
+            .find(|edit| edit.start <= range.start && range.end <= edit.end)
+            .unwrap_or(range);
+        Some(self.moved(range.start)?..self.moved(range.end)?)
+    }
+
+    /// Returns where `position` in the line is in the rewritten match, or
+    /// `None` if it was inside a part that was replaced.
+    fn moved(&self, position: usize) -> Option<usize> {
+        let mut moved = position - self.start;
+        for (range, len) in &self.edits {
+            if range.start < position && position < range.end {
+                return None;
+            }
+            if position >= range.end {
+                moved = moved + len - range.len();
+            }
+        }
+        Some(moved)
+    }
+}
+
+/// Describes where a group matched on a line, for --debug-matches. `label` is
+/// the group's name, or its index if it doesn't have one.
+fn debug_match(
+    line_number: usize,
+    label: &str,
+    range: &std::ops::Range<usize>,
+    text: &[u8],
+) -> String {
+    format!(
+        "line {}: group {} matched {}..{}: {:?}",
+        line_number,
+        label,
+        range.start,
+        range.end,
+        String::from_utf8_lossy(text)
+    )
+}
+
+/// Returns, for each group with the given capture names, whether it's left
+/// alone because of `--named-only`.
+fn ignored_groups<'a>(named_only: bool, names: impl Iterator<Item = Option<&'a str>>) -> Vec<bool> {
+    names.map(|name| named_only && name.is_none()).collect()
+}
+
+/// Adds the spans that style group `i`'s match. Groups without a style get a
+/// color from `palette`, if there are any.
+fn push_group_spans(
+    ops: &mut Ops,
+    (label, group_style): &(String, Option<&GroupStyle>),
+    palette: &[Style],
+    i: usize,
+    m: &GroupMatch,
+) {
+    match group_style {
+        Some(style) => style.push_spans(ops, m, label),
+        None if !palette.is_empty() => ops.push_span(
+            m.start,
+            m.start + m.text.len(),
+            palette[i % palette.len()],
+            label.as_str(),
+        ),
+        None => {}
+    }
+}
+
+/// Adds the span that styles group `i`'s match when it isn't valid UTF-8, with
+/// --bytes. Without any text to look at, its style applies to it as a whole.
+fn push_bytes_group_span(
+    ops: &mut Ops,
+    (label, group_style): &(String, Option<&GroupStyle>),
+    palette: &[Style],
+    i: usize,
+    range: &std::ops::Range<usize>,
+    occurrence: usize,
+) {
+    let style = match group_style {
+        Some(style) => style.resolve(None, occurrence),
+        None if !palette.is_empty() => palette[i % palette.len()],
+        None => return,
+    };
+    ops.push_span(range.start, range.end, style, label.as_str());
+}
+
+/// The commands from --style-cmd, and the styles they've already given.
+struct StyleCommands {
+    /// The command for each group that has one, by index.
+    commands: HashMap<usize, String>,
+    cache: HashMap<(usize, String), Style>,
+    /// The attributes commands' styles can't use, with --safe-styles.
+    disallowed: Vec<UnsafeAttribute>,
+}
+
+impl StyleCommands {
+    fn new(
+        style_cmd: &[String],
+        groups: &[(String, Option<&GroupStyle>)],
+        disallowed: Vec<UnsafeAttribute>,
+    ) -> Result<Self> {
+        let mut commands = HashMap::new();
+        for chunk in style_cmd.chunks(2) {
+            let (group, command) = (&chunk[0], &chunk[1]);
+            let i = groups
+                .iter()
+                .enumerate()
+                .skip(1)
+                .find(|(i, (label, _))| label == group || i.to_string() == *group)
+                .map(|(i, _)| i)
+                .context(format!(
+                    "there is a --style-cmd for \"{}\" but no group has that name",
+                    group
+                ))?;
+            commands.insert(i, command.clone());
+        }
+        Ok(StyleCommands {
+            commands,
+            cache: HashMap::new(),
+            disallowed,
+        })
+    }
+
+    /// Returns the style the command for group `i` gives `text`, if the group
+    /// has a command.
+    fn style(&mut self, i: usize, text: &str) -> Result<Option<Style>> {
+        let Some(command) = self.commands.get(&i) else {
+            return Ok(None);
+        };
+        let key = (i, text.to_string());
+        if let Some(style) = self.cache.get(&key) {
+            return Ok(Some(*style));
+        }
+
+        let (shell, flag) = if cfg!(windows) {
+            ("cmd", "/C")
+        } else {
+            ("sh", "-c")
+        };
+        let mut child = Command::new(shell)
+            .args([flag, command])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context(format!("failed to run \"{}\"", command))?;
+        // Dropping stdin closes it, so the command sees the end of its input.
+        // Commands don't have to read it, so it may already be closed.
+        match child.stdin.take().unwrap().write_all(text.as_bytes()) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => {}
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(format!(
+                "\"{}\" failed with {}",
+                command, output.status
+            )));
+        }
+        let style = String::from_utf8_lossy(&output.stdout);
+        let style = match style.trim() {
+            "" => Style::new(),
+            style => {
+                check_safe_style(style, &self.disallowed)?;
+                parse_style(style)?
+            }
+        };
+        self.cache.insert(key, style);
+        Ok(Some(style))
+    }
+
+    /// Adds the spans for a group's match, styled by its command if it has
+    /// one, and by `push_group_spans` otherwise.
+    fn push_spans(
+        &mut self,
+        ops: &mut Ops,
+        group: &(String, Option<&GroupStyle>),
+        palette: &[Style],
+        i: usize,
+        m: &GroupMatch,
+    ) -> Result<()> {
+        match self.style(i, m.text)? {
+            Some(style) => ops.push_span(m.start, m.start + m.text.len(), style, group.0.as_str()),
+            None => push_group_spans(ops, group, palette, i, m),
+        }
+        Ok(())
+    }
+}
+
+/// Writes a line with the label of each group in `groups`, styled the way the
+/// group's matches are.
+fn write_legend(
+    output: &mut impl Write,
+    groups: &[(String, Option<&GroupStyle>)],
+    palette: &[Style],
+    render: Render,
+) -> Result<()> {
+    let mut ops = Ops::default();
+    let mut legend = String::new();
+    for (i, group) in groups.iter().enumerate().skip(1) {
+        if !legend.is_empty() {
+            legend.push(' ');
+        }
+        let m = GroupMatch {
+            start: legend.len(),
+            text: &group.0,
+            key: None,
+            occurrence: 0,
+        };
+        push_group_spans(&mut ops, group, palette, i, &m);
+        legend.push_str(&group.0);
+    }
+    write_styled(output, legend.as_bytes(), &ops, render)?;
+    writeln!(output)?;
+    Ok(())
+}
+
+/// An iterator that can look at items before reaching them.
+struct Lookahead<I: Iterator> {
+    iter: I,
+    ahead: VecDeque<I::Item>,
+}
+
+impl<I: Iterator> Lookahead<I> {
+    fn new(iter: I) -> Self {
+        Lookahead {
+            iter,
+            ahead: VecDeque::new(),
+        }
+    }
+
+    /// Returns up to the next `n` items, without moving past them.
+    fn peek(&mut self, n: usize) -> impl Iterator<Item = &I::Item> {
+        while self.ahead.len() < n {
+            let Some(item) = self.iter.next() else {
+                break;
+            };
+            self.ahead.push_back(item);
+        }
+        self.ahead.iter().take(n)
+    }
+}
+
+impl<I: Iterator> Iterator for Lookahead<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        self.ahead.pop_front().or_else(|| self.iter.next())
+    }
+}
+
+/// Counts the groups styled on each line, for --max-groups-per-line.
+struct GroupLimit {
+    max: usize,
+    count: usize,
+    warned: bool,
+}
+
+impl GroupLimit {
+    fn new(max: usize) -> Self {
+        GroupLimit {
+            max,
+            count: 0,
+            warned: false,
+        }
+    }
+
+    fn next_line(&mut self) {
+        self.count = 0;
+    }
+
+    /// Returns whether another group on the line can be styled, warning the
+    /// first time one can't.
+    fn allow(&mut self, line_number: usize) -> bool {
+        self.count += 1;
+        if self.count <= self.max {
+            return true;
+        }
+        if !self.warned {
+            eprintln!(
+                "recolor: line {} has more than {} groups, only styling the first {}",
+                line_number, self.max, self.max
+            );
+            self.warned = true;
+        }
+        false
+    }
+}
+
+/// Writes to `inner`, or with --reverse-lines, holds on to what's written for
+/// each line until the end of the input, then writes the lines last first.
+/// A line's output is kept in one piece, even if a template puts newlines in
+/// it.
+struct ReverseLines<W: Write> {
+    inner: W,
+    /// What's been written for each line so far, when reversing.
+    lines: Option<Vec<Vec<u8>>>,
+}
+
+impl<W: Write> ReverseLines<W> {
+    fn new(inner: W, enabled: bool) -> Self {
+        ReverseLines {
+            inner,
+            lines: enabled.then(Vec::new),
+        }
+    }
+
+    /// Starts another line, which everything written from now on is part of.
+    fn start_line(&mut self) {
+        if let Some(ref mut lines) = self.lines {
+            lines.push(Vec::new());
+        }
+    }
+
+    /// Writes the lines held on to so far, last first.
+    fn write_reversed(&mut self) -> io::Result<()> {
+        if let Some(ref mut lines) = self.lines {
+            for line in lines.drain(..).rev() {
+                self.inner.write_all(&line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ReverseLines<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.lines.as_mut().and_then(|lines| lines.last_mut()) {
+            Some(line) => {
+                line.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            None => self.inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Remembers the lines seen so far, to spot ones that repeat.
+struct Duplicates<T> {
+    consecutive_only: bool,
+    previous: Option<T>,
+    seen: HashSet<T>,
+}
+
+impl<T: Clone + Eq + std::hash::Hash> Duplicates<T> {
+    fn new(consecutive_only: bool) -> Self {
+        Duplicates {
+            consecutive_only,
+            previous: None,
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Returns whether `line` is a duplicate, and remembers it for next time.
+    fn check(&mut self, line: &T) -> bool {
+        if self.consecutive_only {
+            let duplicate = self.previous.as_ref() == Some(line);
+            self.previous = Some(line.clone());
+            duplicate
+        } else {
+            !self.seen.insert(line.clone())
+        }
+    }
+}
+
+/// Reports how far through its input `run` is to stderr, at most once a
+/// second.
+struct Progress {
+    enabled: bool,
+    lines: u64,
+    start: Instant,
+    last_report: Instant,
+}
+
+impl Progress {
+    fn new(enabled: bool) -> Self {
+        let now = Instant::now();
+        Progress {
+            enabled,
+            lines: 0,
+            start: now,
+            last_report: now,
+        }
+    }
+
+    /// Counts a line, reporting progress if it's been long enough since the
+    /// last report.
+    fn tick(&mut self) -> io::Result<()> {
+        self.tick_with(Instant::now, &mut io::stderr())
+    }
+
+    /// Like `tick`, but getting the time from `now` and writing the report to
+    /// `output`. Checking the time for every line would be wasteful, so it
+    /// only happens every so often.
+    fn tick_with(
+        &mut self,
+        now: impl FnOnce() -> Instant,
+        output: &mut impl Write,
+    ) -> io::Result<()> {
+        self.lines += 1;
+        if !self.enabled || !self.lines.is_multiple_of(1024) {
+            return Ok(());
+        }
+        let now = now();
+        if now.duration_since(self.last_report) >= Duration::from_secs(1) {
+            self.last_report = now;
+            write!(output, "\r{}", self.report(now))?;
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        self.finish_with(Instant::now(), &mut io::stderr())
+    }
+
+    fn finish_with(&self, now: Instant, output: &mut impl Write) -> io::Result<()> {
+        if self.enabled {
+            writeln!(output, "\r{}", self.report(now))?;
+        }
+        Ok(())
+    }
+
+    fn report(&self, now: Instant) -> String {
+        let seconds = now.duration_since(self.start).as_secs_f64();
+        let rate = if seconds > 0.0 {
+            self.lines as f64 / seconds
+        } else {
+            0.0
+        };
+        format!("{} lines, {:.0} lines/s", self.lines, rate)
+    }
+}
+
+/// What `run` is doing, for --profile.
+#[derive(Clone, Copy)]
+enum Phase {
+    Reading,
+    Matching,
+    Styling,
+    Writing,
+}
+
+/// Adds up how long `run` spends in each phase, for --profile. When it's not
+/// enabled the time is never checked.
+struct Profile {
+    enabled: bool,
+    phase: Phase,
+    since: Instant,
+    totals: [Duration; 4],
+}
+
+impl Profile {
+    fn new(enabled: bool) -> Self {
+        Profile {
+            enabled,
+            phase: Phase::Reading,
+            since: Instant::now(),
+            totals: [Duration::ZERO; 4],
+        }
+    }
+
+    /// Counts the time since the last switch towards the phase `run` was in,
+    /// and starts timing `phase`.
+    fn switch(&mut self, phase: Phase) {
+        if !self.enabled {
+            return;
+        }
+        let now = Instant::now();
+        self.totals[self.phase as usize] += now - self.since;
+        self.phase = phase;
+        self.since = now;
+    }
+
+    fn finish(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        self.switch(Phase::Reading);
+        let total: Duration = self.totals.iter().sum();
+        let names = ["reading", "matching", "styling", "writing"];
+        for (name, time) in names.iter().zip(self.totals) {
+            let percent = if total.is_zero() {
+                0.0
+            } else {
+                time.as_secs_f64() / total.as_secs_f64() * 100.0
+            };
+            eprintln!("{:<8}  {:>12.3?}  {:>5.1}%", name, time, percent);
+        }
+    }
+}
+
+/// Adds up how much of the input the regex matched, for --count-bytes and
+/// --count-chars.
+struct Coverage {
+    enabled: bool,
+    chars: bool,
+    total: usize,
+    matched: usize,
+    groups: Vec<usize>,
+}
+
+impl Coverage {
+    fn new(bytes: bool, chars: bool, groups: usize) -> Self {
+        Coverage {
+            enabled: bytes || chars,
+            chars,
+            total: 0,
+            matched: 0,
+            groups: vec![0; groups],
+        }
+    }
+
+    fn len<H: Haystack + ?Sized>(&self, text: &H) -> usize {
+        if self.chars {
+            text.char_indices().count()
+        } else {
+            text.len()
+        }
+    }
+
+    fn add_line<H: Haystack + ?Sized>(&mut self, line: &H) {
+        if self.enabled {
+            self.total += self.len(line);
+        }
+    }
+
+    fn add_match<H: Haystack + ?Sized>(&mut self, text: &H) {
+        if self.enabled {
+            self.matched += self.len(text);
+        }
+    }
+
+    fn add_group<H: Haystack + ?Sized>(&mut self, i: usize, text: &H) {
+        if self.enabled {
+            self.groups[i] += self.len(text);
+        }
+    }
+
+    fn finish(&self, groups: &[(String, Option<&GroupStyle>)]) {
+        if self.enabled {
+            eprint!("{}", self.report(groups));
+        }
+    }
+
+    fn report(&self, groups: &[(String, Option<&GroupStyle>)]) -> String {
+        let unit = if self.chars { "chars" } else { "bytes" };
+        let percent = if self.total > 0 {
+            self.matched as f64 * 100.0 / self.total as f64
+        } else {
+            0.0
+        };
+        let mut report = format!(
+            "matched {} of {} {} ({:.1}%)\n",
+            self.matched, self.total, unit, percent
+        );
+        for ((label, _), len) in groups.iter().zip(&self.groups).skip(1) {
+            report.push_str(&format!("  {}: {} {}\n", label, len, unit));
+        }
+        report
+    }
+}
+
+/// The most characters wide a bar in the --stats histogram can be.
+const STATS_BAR_WIDTH: usize = 30;
+
+/// Counts how long each group's matches are for --stats, in buckets that
+/// double in size: 0, 1, 2-3, 4-7 and so on.
+struct Stats {
+    enabled: bool,
+    buckets: Vec<Vec<usize>>,
+}
+
+impl Stats {
+    fn new(enabled: bool, groups: usize) -> Self {
+        Stats {
+            enabled,
+            buckets: vec![Vec::new(); groups],
+        }
+    }
+
+    fn add_group<H: Haystack + ?Sized>(&mut self, i: usize, text: &H) {
+        if !self.enabled {
+            return;
+        }
+        let len = text.char_indices().count();
+        let bucket = match len {
+            0 => 0,
+            len => len.ilog2() as usize + 1,
+        };
+        let buckets = &mut self.buckets[i];
+        if buckets.len() <= bucket {
+            buckets.resize(bucket + 1, 0);
+        }
+        buckets[bucket] += 1;
+    }
+
+    fn finish(&self, groups: &[(String, Option<&GroupStyle>)], palette: &[Style]) -> Result<()> {
+        if self.enabled {
+            let render = Render {
+                no_color: !io::stderr().is_terminal(),
+                ..Default::default()
+            };
+            io::stderr().write_all(&self.report(groups, palette, render)?)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a histogram for each group, with the bars in the group's
+    /// style.
+    fn report(
+        &self,
+        groups: &[(String, Option<&GroupStyle>)],
+        palette: &[Style],
+        render: Render,
+    ) -> Result<Vec<u8>> {
+        let mut report = Vec::new();
+        for (i, ((label, style), buckets)) in groups.iter().zip(&self.buckets).enumerate().skip(1) {
+            let matches: usize = buckets.iter().sum();
+            writeln!(report, "{}: {} matches", label, matches)?;
+            let style = match style {
+                Some(style) => style.resolve(None, 0),
+                None if !palette.is_empty() => palette[i % palette.len()],
+                None => Style::new(),
+            };
+            let most = buckets.iter().max().copied().unwrap_or(0);
+            let first = buckets.iter().position(|count| *count > 0).unwrap_or(0);
+            for (bucket, count) in buckets.iter().enumerate().skip(first) {
+                let lengths = match bucket {
+                    0 | 1 => bucket.to_string(),
+                    _ => format!("{}-{}", 1 << (bucket - 1), (1 << bucket) - 1),
+                };
+                write!(report, "  {:>9} ", lengths)?;
+                let width = (count * STATS_BAR_WIDTH).div_ceil(most);
+                render.write_text(&mut report, &"█".repeat(width), style)?;
+                writeln!(report, " {}", count)?;
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// Folds the styles from `--word` and `--styles-file` into `args.styles`.
+fn expand_styles(args: &mut Args) -> Result<()> {
+    if !args.words.is_empty() {
+        let (regex, styles, labels) = words_regex(&args.words)?;
+        args.regex = Some(regex);
+        args.styles.extend(styles);
+        args.group_labels = labels;
+    }
+    if let Some(ref style) = args.urls {
+        args.regex = Some(URL_REGEX.to_string());
+        args.styles.push(format!("url={}", style));
+    }
+
+    // Later styles for the same key replace earlier ones, so files go first
+    // in the order they were given, then the command line.
+    let mut file_styles = Vec::new();
+    if let Some(ref path) = args.rules {
+        let contents =
+            std::fs::read_to_string(path).context(format!("failed to read {}", path.display()))?;
+        let (regex, styles) =
+            parse_rules(&contents).context(format!("invalid rules file {}", path.display()))?;
+        // The regex comes from the file, so the first argument is a style.
+        if let Some(style) = args.regex.replace(regex) {
+            args.styles.insert(0, style);
+        }
+        file_styles.extend(styles);
+    }
+    for path in &args.styles_file {
+        let contents =
+            std::fs::read_to_string(path).context(format!("failed to read {}", path.display()))?;
+        file_styles.extend(
+            parse_styles_file(&contents)
+                .context(format!("invalid styles file {}", path.display()))?,
+        );
+    }
+    file_styles.append(&mut args.styles);
+    args.styles = file_styles;
+
+    if !args.config.styles.is_empty() {
+        let mut styles = args.config.styles.clone();
+        styles.append(&mut args.styles);
+        args.styles = styles;
+    }
+    if args.capture_env {
+        expand_env_styles(&mut args.styles, |name| std::env::var(name).ok())?;
+    }
+    if args.safe_styles {
+        for style in &args.styles {
+            let style = style
+                .split_once('=')
+                .map_or(style.as_str(), |(_, style)| style);
+            check_safe_style(style, &args.unsafe_attributes)?;
+        }
+        for style in option_styles(args) {
+            check_safe_style(style, &args.unsafe_attributes)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the styles given to options, like --line-style, rather than to
+/// groups, for --safe-styles. Ones left at their defaults are recolor's own
+/// rather than from somewhere that might not be trusted, so are left out.
+fn option_styles(args: &Args) -> Vec<&str> {
+    let command = Args::command();
+    let is_default = |id: &str, style: &str| {
+        command
+            .get_arguments()
+            .any(|arg| arg.get_id() == id && arg.get_default_values().iter().any(|d| d == style))
+    };
+    let mut styles: Vec<&str> = [
+        ("timestamp_style", &args.timestamp_style),
+        ("duplicate_style", &args.duplicate_style),
+        ("checksum_style", &args.checksum_style),
+        ("filename_style", &args.filename_style),
+        ("max_width_style", &args.max_width_style),
+    ]
+    .into_iter()
+    .filter(|(id, style)| !is_default(id, style))
+    .map(|(_, style)| style.as_str())
+    .collect();
+    styles.extend(
+        [
+            &args.line_style,
+            &args.prefix_style,
+            &args.suffix_style,
+            &args.fallback_style,
+            &args.urls,
+            &args.default_palette,
+        ]
+        .into_iter()
+        .flatten()
+        .map(String::as_str),
+    );
+    // --between takes a start, an end and a style each time.
+    styles.extend(args.between.iter().skip(2).step_by(3).map(String::as_str));
+    styles.extend(args.config.palette.iter().map(String::as_str));
+    styles
+}
+
+/// Replaces `key=$NAME` style references in `styles` with the value `env`
+/// gives for NAME, for --capture-env.
+fn expand_env_styles(styles: &mut [String], env: impl Fn(&str) -> Option<String>) -> Result<()> {
+    for entry in styles.iter_mut() {
+        let Some((key, value)) = entry.split_once('=') else {
+            continue;
+        };
+        let Some(reference) = value.strip_prefix('$') else {
+            continue;
+        };
+        let (name, fallback) = match reference
+            .strip_prefix('{')
+            .and_then(|r| r.strip_suffix('}'))
+        {
+            Some(braced) => match braced.split_once(":-") {
+                Some((name, fallback)) => (name, Some(fallback)),
+                None => (braced, None),
+            },
+            None => (reference, None),
+        };
+        let style = env(name)
+            .filter(|style| !style.is_empty())
+            .or(fallback.map(str::to_string))
+            .context(format!(
+                "the style for {} is ${} but it isn't set",
+                key, name
+            ))?;
+        *entry = format!("{}={}", key, style);
+    }
+    Ok(())
+}
+
+/// Returns the styles to cycle through for groups that don't have one, which
+/// is empty if they shouldn't be styled at all.
+fn palette(args: &Args) -> Result<Vec<Style>> {
+    if args.no_default_color {
+        return Ok(Vec::new());
+    }
+    if let Some(ref style) = args.fallback_style {
+        return Ok(vec![parse_style(style)?]);
+    }
+    let mut palette = if let Some(ref colors) = args.default_palette {
+        let palette = split_top_level(colors)
+            .into_iter()
+            .map(|color| parse_style(color.trim()))
+            .collect::<Result<Vec<_>>>()
+            .context(format!("invalid palette: \"{}\"", colors))?;
+        if palette.iter().all(|style| style.is_plain()) {
+            return Err(anyhow::anyhow!(format!(
+                "invalid palette: \"{}\", it has no colors",
+                colors
+            )));
+        }
+        palette
+    } else if let Some(palette) = image_palette(args)? {
+        palette
+    } else if args.config.palette.is_empty() {
+        DEFAULT_STYLES.clone()
+    } else {
+        args.config
+            .palette
+            .iter()
+            .map(|style| parse_style(style))
+            .collect::<Result<_>>()
+            .context("invalid palette in config file")?
+    };
+    if args.reverse_palette {
+        palette.reverse();
+    }
+    if !palette.is_empty() {
+        let len = palette.len();
+        palette.rotate_left(args.palette_offset % len);
+    }
+    Ok(palette)
+}
+
+/// Returns the colors from --palette-from, if it was given.
+#[cfg(feature = "image-palette")]
+fn image_palette(args: &Args) -> Result<Option<Vec<Style>>> {
+    let Some(ref path) = args.palette_from else {
+        return Ok(None);
+    };
+    if args.palette_size == 0 {
+        return Err(anyhow::anyhow!("--palette-size must be at least 1"));
+    }
+    let colors = image_palette::from_image(path, args.palette_size)?;
+    if colors.is_empty() {
+        return Err(anyhow::anyhow!(format!(
+            "{} has no colors to use",
+            path.display()
+        )));
+    }
+    Ok(Some(
+        colors
+            .into_iter()
+            .map(|(r, g, b)| owo_colors::Style::new().truecolor(r, g, b).into())
+            .collect(),
+    ))
+}
+
+#[cfg(not(feature = "image-palette"))]
+fn image_palette(_: &Args) -> Result<Option<Vec<Style>>> {
+    Ok(None)
+}
+
+/// Returns the palette rearranged so that the default style for each group
+/// comes from a hash of its name, or its position if it doesn't have one.
+/// Indexing the result by group number gives that group's style.
+fn palette_by_name(palette: Vec<Style>, names: Vec<Option<&str>>) -> Vec<Style> {
+    if palette.is_empty() {
+        return palette;
+    }
+    names
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let index = match name {
+                // FNV-1a, which unlike std's hasher is the same everywhere.
+                Some(name) => name.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+                    (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+                }) as usize,
+                None => i,
+            };
+            palette[index % palette.len()]
+        })
+        .collect()
+}
+
+fn filter_options(args: &Args) -> ansi::FilterOptions {
+    ansi::FilterOptions {
+        tmux_passthrough: args.tmux_passthrough,
+        depth: if args.ascii_only {
+            ansi::ColorDepth::Ansi16
+        } else {
+            args.colors.unwrap_or_default()
+        },
+        wrap_width: args.terminal_width.filter(|_| args.repeat_style_on_wrap),
+        osc: args.osc,
+        compact: args.compact_escapes,
+    }
+}
+
+/// Something to color, and the name --with-filename gives it.
+struct Input<'a> {
+    /// The file's name, or `None` for standard input.
+    name: Option<String>,
+    reader: Box<dyn BufRead + 'a>,
+}
+
+/// Colors `input` to `output`, returning the number of times the regex
+/// matched.
+fn run(input: impl BufRead, output: impl Write, args: Args) -> Result<usize> {
+    let input = Input {
+        name: None,
+        reader: Box::new(input),
+    };
+    run_all(std::iter::once(Ok(input)), output, args)
+}
+
+/// Like `run`, but colors each of `inputs` in turn, as one stream of output.
+/// Totals like --count-bytes cover all of them.
+fn run_all<'a>(
+    inputs: impl Iterator<Item = Result<Input<'a>>>,
+    output: impl Write,
+    args: Args,
+) -> Result<usize> {
+    if args.output == OutputFormat::Ndjson {
+        return run_ndjson(inputs, output, args);
+    }
+    if args.bytes {
+        run_lines::<[u8]>(inputs, output, args)
+    } else {
+        run_lines::<str>(inputs, output, args)
+    }
+}
+
+/// Does the work of `run_all`, matching the regex against each line as `H`.
+fn run_lines<'a, H: Haystack + ?Sized>(
+    inputs: impl Iterator<Item = Result<Input<'a>>>,
+    output: impl Write,
+    mut args: Args,
+) -> Result<usize> {
+    let output = ansi::Filter::new(output, filter_options(&args));
+    let mut output = ReverseLines::new(output, args.reverse_lines);
+    expand_styles(&mut args)?;
+    let regex = H::build_regex(&args)?;
+    let names = H::capture_names(&regex);
+    let mut palette = palette(&args)?;
+    if args.color_by_name {
+        palette = palette_by_name(palette, names.clone());
+    }
+    let mut styles = parse_styles(args.styles)?;
+    if args.truncate_padded {
+        styles.values_mut().for_each(GroupStyle::truncate_padding);
+    }
+    let groups = groups(names.clone(), &styles, &args.group_labels)?;
+    let humanizing = groups
+        .iter()
+        .any(|(_, style)| style.and_then(GroupStyle::humanize).is_some());
+    if humanizing && args.bytes {
+        return Err(anyhow::anyhow!(
+            "humanize styles can't be used with --bytes"
+        ));
+    }
+    if humanizing && (args.window.is_some() || !args.between.is_empty()) {
+        return Err(anyhow::anyhow!(
+            "humanize styles can't be used with --window or --between"
+        ));
+    }
+    if humanizing && args.ignore_ansi_in_matching {
+        return Err(anyhow::anyhow!(
+            "humanize styles can't be used with --ignore-ansi-in-matching"
+        ));
+    }
+    let ignored = ignored_groups(args.named_only, names.iter().copied());
+    let disallowed = if args.safe_styles {
+        args.unsafe_attributes.clone()
+    } else {
+        Vec::new()
+    };
+    let template = args
+        .replace
+        .as_deref()
+        .map(|template| parse_template(template, &names, &disallowed))
+        .transpose()?;
+    let mut style_commands = StyleCommands::new(&args.style_cmd, &groups, disallowed)?;
+    let timestamp_regex = match args.timestamp_pattern {
+        Some(ref pattern) => parse_timestamp_regex(pattern)?,
+        None => DEFAULT_TIMESTAMP_REGEX.clone(),
+    };
+    let timestamp_style = parse_style(&args.timestamp_style)?;
+    let checksum_style = parse_style(&args.checksum_style)?;
+    let between = parse_between(&args.between)?;
+    let columns = args.columns.as_deref().map(parse_range).transpose()?;
+    let line_ranges = parse_line_ranges(&args.line_range)?;
+    let only_when = args
+        .only_when
+        .as_deref()
+        .map(H::new_regex)
+        .transpose()
+        .context("invalid --only-when regex")?;
+    let line_style = args.line_style.as_deref().map(parse_style).transpose()?;
+    let prefix_style = args.prefix_style.as_deref().map(parse_style).transpose()?;
+    let suffix_style = args.suffix_style.as_deref().map(parse_style).transpose()?;
+    let filename_style = parse_style(&args.filename_style)?;
+    let duplicate_style = parse_style(&args.duplicate_style)?;
+    let max_width_style = parse_style(&args.max_width_style)?;
+    let mut duplicates = Duplicates::new(args.consecutive_only);
+    let mut group_limit = GroupLimit::new(args.max_groups_per_line);
+    let group_separator = unescape(&args.group_separator).context("invalid --group-separator")?;
+    let links = args.urls.is_some() && args.output != OutputFormat::Svg;
+    let delimiters = delimiters(
+        args.bidi_isolate,
+        args.delimit_matches.as_deref(),
+        &args.group_delimiters,
+        &groups,
+    )?;
+    let plain = Render {
+        dry_run: args.dry_run,
+        no_color: args.color == ColorChoice::Never,
+        mode: args.style_mode,
+        merge_adjacent: args.merge_adjacent_same_style,
+        delimiters: delimiters.as_ref(),
+        ..Default::default()
+    };
+
+    let frame = Duration::try_from_secs_f64(1.0 / args.fps)
+        .context(format!("invalid --fps: {}", args.fps))?;
+    if args.tab_stop == Some(0) {
+        return Err(anyhow::anyhow!("--tab-stop must be at least 1"));
+    }
+    if args.fade == Some(0) {
+        return Err(anyhow::anyhow!("--fade must be at least 1"));
+    }
+    if args.reset_on_start {
+        plain.write_reset(&mut output)?;
+    }
+    if args.legend {
+        write_legend(&mut output, &groups, &palette, plain)?;
+    }
+
+    let mut ops;
+    let mut matches = 0;
+    let mut coverage = Coverage::new(args.count_bytes, args.count_chars, groups.len());
+    let mut stats = Stats::new(args.stats, groups.len());
+    let mut profile = Profile::new(args.profile);
+    let mut progress = Progress::new(args.progress);
+    // With --no-trailing-newline, each line's newline waits until there's
+    // another line to write, which may be in the next input. With --animate,
+    // the line is cleared instead.
+    let mut newline_pending = false;
+
+    for input in inputs {
+        let Input { name, reader } = input?;
+        let mut since_match = None;
+        let mut previous_timestamp: Option<f64> = None;
+        let mut previous_blank = false;
+
+        // Spans from matches that started on earlier lines, moved to where
+        // they are on the current one, and how far into it they matched.
+        let mut carried = Ops::default();
+        let mut carried_end = 0;
+        let mut lines = Lookahead::new(read_lines::<H>(reader).enumerate());
+
+        loop {
+            profile.switch(Phase::Reading);
+            let Some((line_number, line)) = lines.next() else {
+                break;
+            };
+            profile.switch(Phase::Styling);
+            ops = std::mem::take(&mut carried);
+            progress.tick()?;
+
+            let mut line = match line {
+                Err(e) if args.keep_going && e.kind() == io::ErrorKind::InvalidData => {
+                    eprintln!("recolor: skipping line {}: {}", line_number + 1, e);
+                    carried_end = 0;
+                    continue;
+                }
+                line => line?,
+            };
+            let checksum = args.checksum.map(|checksum| checksum.of(line.as_bytes()));
+            // Escape sequences taken out for --ignore-ansi-in-matching, kept
+            // aside until the line is done being cut short.
+            let mut input_escapes = Vec::new();
+            if args.ignore_ansi_in_matching {
+                if let Some(text) = line.to_str() {
+                    let (text, escapes) = ansi::split_escapes(text);
+                    line = text.into();
+                    input_escapes = escapes;
+                }
+            }
+            if args.trim {
+                line = line.trim().to_owned();
+            }
+            if args.squeeze_blank {
+                let blank = line.is_empty();
+                if blank && previous_blank {
+                    continue;
+                }
+                previous_blank = blank;
+            }
+            let duplicate = args.highlight_duplicates && duplicates.check(&line);
+            output.start_line();
+            if newline_pending && args.animate {
+                write!(output, "\r\x1b[2K")?;
+                newline_pending = false;
+            } else if newline_pending {
+                writeln!(output)?;
+                newline_pending = false;
+            }
+
+            let mut truncated = false;
+            if let Some(max) = args.max_line_length {
+                // Escape sequences in the text don't count towards its length,
+                // and are kept whole.
+                match line.to_str().filter(|_| !args.bytes) {
+                    Some(text) => {
+                        if let Some(text) = ansi::truncate(text, max) {
+                            line = text.into();
+                            truncated = true;
+                        }
+                    }
+                    None => {
+                        let end = line.char_indices().nth(max).map(|(end, _)| end);
+                        if let Some(end) = end {
+                            H::truncate(&mut line, end);
+                            truncated = true;
+                        }
+                    }
+                }
+                // The input's escapes past the cut are still written, so that
+                // its styles are reset.
+                for (position, _) in &mut input_escapes {
+                    *position = (*position).min(line.len());
+                }
+            }
+
+            if args.with_filename {
+                let filename = name.as_deref().unwrap_or("(standard input)");
+                plain.write_text(&mut output, filename, filename_style)?;
+                write!(output, ":")?;
+            }
+
+            if let Some(ref prefix) = args.prefix {
+                plain.write_text(&mut output, prefix, prefix_style.unwrap_or_default())?;
+            }
+
+            if let Some(ref checksum) = checksum {
+                if args.checksum_position == ChecksumPosition::Start {
+                    plain.write_text(&mut output, checksum, checksum_style)?;
+                    write!(output, " ")?;
+                }
+            }
+
+            if args.timestamp_relative {
+                let timestamp = line
+                    .to_str()
+                    .and_then(|text| parse_timestamp(&timestamp_regex, text));
+                if let Some((len, seconds, has_date)) = timestamp {
+                    let mut delta = seconds - previous_timestamp.unwrap_or(seconds);
+                    if delta < 0.0 && !has_date {
+                        // Without a date, going backwards means we've passed midnight.
+                        delta += 86400.0;
+                    }
+                    previous_timestamp = Some(seconds);
+                    let delta = format!("+{:.3}s", delta);
+                    if args.dry_run {
+                        write!(output, "«timestamp:{}»", delta)?;
+                    } else {
+                        plain.write_text(&mut output, &delta, timestamp_style)?;
+                    }
+                    H::replace_range(&mut line, 0..len, H::from_text(""));
+                    for (position, _) in &mut input_escapes {
+                        *position = position.saturating_sub(len);
+                    }
+                }
+            }
+            for (position, escape) in input_escapes {
+                ops.push_escape(position, escape);
+            }
+
+            if let Some(text) = line.to_str() {
+                for (start, end, style) in &between {
+                    for (from, to) in between_spans(start, end, text) {
+                        ops.push_span(from, to, *style, "between");
+                    }
+                }
+            }
+
+            let mut matched = false;
+            group_limit.next_line();
+            let mut replaced = H::Line::from(String::new());
+            let mut replaced_up_to = 0;
+            let mut occurrences = vec![0; groups.len()];
+            let (first, last) = columns
+                .map(|columns| column_range(&*line, columns, args.tab_stop))
+                .unwrap_or((0, usize::MAX));
+            coverage.add_line(&*line);
+            let joined;
+            let haystack: &H = match args.window {
+                Some(size) => {
+                    let ahead = lines.peek(size.saturating_sub(1));
+                    joined = H::join_lines(
+                        &std::iter::once(&*line)
+                            .chain(ahead.filter_map(|(_, next)| next.as_deref().ok()))
+                            .collect::<Vec<_>>(),
+                    );
+                    &joined
+                }
+                None => &line,
+            };
+            let mut window_end = carried_end;
+            let mut match_ranges = Vec::new();
+            let mut group_ranges = Vec::new();
+            if only_when
+                .as_ref()
+                .is_none_or(|gate| H::is_match(gate, &line))
+            {
+                let mut captures = H::captures(&regex, haystack);
+                loop {
+                    profile.switch(Phase::Matching);
+                    let m = captures.next();
+                    profile.switch(Phase::Styling);
+                    let Some(m) = m else {
+                        break;
+                    };
+                    let whole = m.whole();
+                    if whole.start > line.len() {
+                        // It started on a later line, so will be found again there.
+                        break;
+                    }
+                    if whole.start < carried_end || whole.start < first || whole.end > last {
+                        continue;
+                    }
+                    if args.require_all_groups && m.iter().any(|capture| capture.is_none()) {
+                        continue;
+                    }
+                    window_end = window_end.max(whole.end);
+                    coverage.add_match(&haystack[whole.clone()]);
+                    let whole_text = haystack[whole.clone()].to_str().unwrap_or_default();
+                    // Without a template, humanize styles are the only thing that
+                    // changes the text.
+                    let rewrite = haystack
+                        .to_str()
+                        .filter(|_| template.is_none() && humanizing)
+                        .map(|text| Rewrite::new(text, &m, &groups));
+                    if let Some(ref rewrite) = rewrite {
+                        H::push(&mut replaced, &line[replaced_up_to..whole.start]);
+                        match_ranges.push(replaced.len()..replaced.len() + rewrite.text.len());
+                    } else {
+                        match_ranges.push(whole.clone());
+                    }
+                    if links && template.is_none() {
+                        let range = match_ranges.last().unwrap();
+                        ops.push_link(range.start, range.end, whole_text);
+                    }
+                    matched = true;
+                    matches += 1;
+                    for (i, capture) in m.iter().enumerate().skip(1) {
+                        let Some(mat) = capture.filter(|_| !ignored[i]) else {
+                            continue;
+                        };
+                        if !group_limit.allow(line_number + 1) {
+                            continue;
+                        }
+
+                        let group = &haystack[mat.clone()];
+                        if args.debug_matches {
+                            eprintln!(
+                                "{}",
+                                debug_match(line_number + 1, &groups[i].0, &mat, group.as_bytes())
+                            );
+                        }
+                        let occurrence = occurrences[i];
+                        occurrences[i] += 1;
+                        coverage.add_group(i, group);
+                        stats.add_group(i, group);
+                        if template.is_some() {
+                            continue;
+                        }
+                        let (range, text) = match rewrite {
+                            Some(ref rewrite) => {
+                                let Some(std::ops::Range { start, end }) =
+                                    rewrite.moved_range(&mat)
+                                else {
+                                    continue;
+                                };
+                                let at = replaced.len();
+                                (at + start..at + end, Some(&rewrite.text[start..end]))
+                            }
+                            None => (mat.clone(), group.to_str()),
+                        };
+                        let (range, text) = match text.filter(|_| args.first_line_only) {
+                            Some(text) => {
+                                let text = text.split('\n').next().unwrap_or_default();
+                                (range.start..range.start + text.len(), Some(text))
+                            }
+                            None => (range, text),
+                        };
+                        let key = text
+                            .and_then(|text| match_key(haystack, &m, &names, groups[i].1, text));
+                        match text {
+                            Some(text) => {
+                                let group_match = GroupMatch {
+                                    start: range.start,
+                                    text,
+                                    key,
+                                    occurrence,
+                                };
+                                style_commands.push_spans(
+                                    &mut ops,
+                                    &groups[i],
+                                    &palette,
+                                    i,
+                                    &group_match,
+                                )?;
+                            }
+                            None => push_bytes_group_span(
+                                &mut ops, &groups[i], &palette, i, &range, occurrence,
+                            ),
+                        }
+                        group_ranges.push(range);
+                    }
+
+                    if let Some(rewrite) = rewrite {
+                        H::push(&mut replaced, H::from_text(&rewrite.text));
+                        replaced_up_to = whole.end;
+                    }
+                    let Some(ref template) = template else {
+                        continue;
+                    };
+                    H::push(&mut replaced, &line[replaced_up_to..whole.start]);
+                    let link_start = replaced.len();
+                    for part in template {
+                        match part {
+                            TemplatePart::Literal(text) => {
+                                H::push(&mut replaced, H::from_text(text))
+                            }
+                            TemplatePart::StyledLiteral(text, style) => {
+                                let group_match = GroupMatch {
+                                    start: replaced.len(),
+                                    text,
+                                    key: match_key(haystack, &m, &names, Some(style), text),
+                                    occurrence: 0,
+                                };
+                                style.push_spans(&mut ops, &group_match, "template");
+                                H::push(&mut replaced, H::from_text(text));
+                            }
+                            TemplatePart::Group(i) | TemplatePart::StyledGroup(i, _) => {
+                                let Some(mat) = m.get(*i) else {
+                                    continue;
+                                };
+                                let group = haystack[mat].to_str().unwrap_or_default();
+                                // A style from the template replaces the group's own.
+                                let inline = match part {
+                                    TemplatePart::StyledGroup(_, style) => Some(style),
+                                    _ => None,
+                                };
+                                let style = inline.or(groups[*i].1);
+                                let text = style
+                                    .and_then(GroupStyle::humanize)
+                                    .and_then(|humanize| humanize.apply(group))
+                                    .unwrap_or_else(|| group.to_string());
+                                // Group 0 is the whole match, which is only styled
+                                // by the template, like groups left alone by
+                                // --named-only.
+                                if (*i > 0 && !ignored[*i]) || inline.is_some() {
+                                    let group_match = GroupMatch {
+                                        start: replaced.len(),
+                                        text: &text,
+                                        key: match_key(haystack, &m, &names, style, group),
+                                        occurrence: occurrences[*i].saturating_sub(1),
+                                    };
+                                    match inline {
+                                        Some(style) => {
+                                            style.push_spans(&mut ops, &group_match, &groups[*i].0)
+                                        }
+                                        None => style_commands.push_spans(
+                                            &mut ops,
+                                            &groups[*i],
+                                            &palette,
+                                            *i,
+                                            &group_match,
+                                        )?,
+                                    }
+                                    group_ranges.push(replaced.len()..replaced.len() + text.len());
+                                }
+                                H::push(&mut replaced, H::from_text(&text));
+                            }
+                        }
+                    }
+                    if links {
+                        ops.push_link(link_start, replaced.len(), whole_text);
+                    }
+                    replaced_up_to = whole.end;
+                }
+            }
+            since_match = match since_match {
+                _ if matched => Some(0),
+                Some(lines) => Some(lines + 1),
+                None => None,
+            };
+            if template.is_some() || humanizing {
+                H::push(&mut replaced, &line[replaced_up_to..line.len()]);
+                line = replaced;
+            }
+            if let Some(case) = args.normalize_case {
+                for range in group_ranges {
+                    // With --window, groups can carry on past the end of the line.
+                    let range = range.start.min(line.len())..range.end.min(line.len());
+                    if let Some(changed) = line[range.clone()]
+                        .to_str()
+                        .map(|text| change_case(text, case))
+                    {
+                        H::replace_range(&mut line, range, H::from_text(&changed));
+                    }
+                }
+            }
+            if args.window.is_some() {
+                // The next line starts after this one's newline.
+                let next = line.len() + 1;
+                carried = ops.clip(next, usize::MAX);
+                carried_end = window_end.saturating_sub(next);
+                ops = ops.clip(0, line.len());
+            }
+            if args.whole_graphemes {
+                if let Some(text) = line.to_str() {
+                    ops = ops.snap_to_graphemes(text);
+                    for range in &mut match_ranges {
+                        *range = grapheme_end(text, range.start)..grapheme_end(text, range.end);
+                    }
+                }
+            }
+            profile.switch(Phase::Writing);
+            if args.drop_unmatched_lines && !matched {
+                continue;
+            }
+            if args.only_matching {
+                let render = Render {
+                    base: line_style.unwrap_or_default(),
+                    show_whitespace: args.highlight_whitespace,
+                    ..plain
+                };
+                for range in match_ranges {
+                    let ops = ops.clip(range.start, range.end);
+                    write_styled(&mut output, line[range].as_bytes(), &ops, render)?;
+                    write!(output, "{}", group_separator)?;
+                }
+                continue;
+            }
+
+            let render = Render {
+                base: line_style
+                    .filter(|_| matched)
+                    .or(Some(duplicate_style).filter(|_| duplicate))
+                    .or_else(|| line_range_style(&line_ranges, line_number))
+                    .or_else(|| args.fade.and_then(|steps| fade_style(since_match, steps)))
+                    .unwrap_or_default(),
+                show_whitespace: args.highlight_whitespace || args.show_all,
+                overflow: args
+                    .max_width
+                    .and_then(|width| line.char_indices().nth(width))
+                    .map(|(at, _)| (at, max_width_style)),
+                ..plain
+            };
+            match args.collapse_unmatched {
+                Some(ref placeholder) if matched => write_collapsed(
+                    &mut output,
+                    line.as_bytes(),
+                    &ops,
+                    render,
+                    &match_ranges,
+                    placeholder,
+                )?,
+                _ => write_styled(&mut output, line.as_bytes(), &ops, render)?,
+            }
+            if truncated {
+                write!(output, "…")?;
+            }
+            if args.show_all {
+                plain.write_text(&mut output, "$", Style::new().map(|s| s.dimmed()))?;
+            }
+            if let Some(ref checksum) = checksum {
+                if args.checksum_position == ChecksumPosition::End {
+                    write!(output, " ")?;
+                    plain.write_text(&mut output, checksum, checksum_style)?;
+                }
+            }
+            if let Some(ref suffix) = args.suffix {
+                plain.write_text(&mut output, suffix, suffix_style.unwrap_or_default())?;
+            }
+            if args.animate {
+                output.flush()?;
+                std::thread::sleep(frame);
+                newline_pending = true;
+            } else if args.no_trailing_newline {
+                newline_pending = true;
+            } else {
+                writeln!(output)?;
+            }
+        }
+        output.write_reversed()?;
+        output.flush()?;
+    }
+    if args.animate && newline_pending {
+        writeln!(output)?;
+    }
+    output.flush()?;
+    progress.finish()?;
+    coverage.finish(&groups);
+    stats.finish(&groups, &palette)?;
+    profile.finish();
+    Ok(matches)
+}
+
+/// Like `run`, but writes a JSON object for each line instead of styling it,
+/// with the line's number and text and where each group matched in it.
+/// Positions are byte offsets into the text.
+fn run_ndjson<'a>(
+    inputs: impl Iterator<Item = Result<Input<'a>>>,
+    mut output: impl Write,
+    mut args: Args,
+) -> Result<usize> {
+    if args.bytes {
+        return Err(anyhow::anyhow!(
+            "--output ndjson can't be used with --bytes"
+        ));
+    }
+    expand_styles(&mut args)?;
+    let regex = build_regex(&args)?;
+    let palette = palette(&args)?;
+    let styles = parse_styles(args.styles)?;
+    let groups = groups(regex.capture_names().collect(), &styles, &args.group_labels)?;
+    let ignored = ignored_groups(args.named_only, regex.capture_names());
+
+    let mut matches = 0;
+    for input in inputs {
+        for (line_number, line) in input?.reader.lines().enumerate() {
+            let line = match line {
+                Err(e) if args.keep_going && e.kind() == io::ErrorKind::InvalidData => {
+                    eprintln!("recolor: skipping line {}: {}", line_number + 1, e);
+                    continue;
+                }
+                line => line?,
+            };
+            let mut descriptors = Vec::new();
+            for m in regex.captures_iter(&line) {
+                matches += 1;
+                for (i, capture) in m.iter().enumerate().skip(1) {
+                    let Some(mat) = capture else {
+                        continue;
+                    };
+                    let (label, style) = &groups[i];
+                    descriptors.push(format!(
+                        r#"{{"group":{},"start":{},"end":{},"styled":{}}}"#,
+                        json_string(label),
+                        mat.start(),
+                        mat.end(),
+                        !ignored[i] && (style.is_some() || !palette.is_empty())
+                    ));
+                }
+            }
+            writeln!(
+                output,
+                r#"{{"line":{},"text":{},"matches":[{}]}}"#,
+                line_number + 1,
+                json_string(&line),
+                descriptors.join(",")
+            )?;
+        }
+    }
+    output.flush()?;
+    Ok(matches)
+}
+
+/// Returns `s` as a JSON string, quoted and escaped.
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Runs the whole pipeline over `input`, returning the colored output.
+///
+/// ```
+/// use clap::Parser;
+///
+/// let args = recolor::Args::parse_from(["recolor", "(b)", "--color", "never"]);
+/// assert_eq!(recolor::process_str("abc", &args).unwrap(), "abc\n");
+/// ```
+pub fn process_str(input: &str, args: &Args) -> Result<String> {
+    let mut output = Vec::new();
+    run(input.as_bytes(), &mut output, args.clone())?;
+    if args.output == OutputFormat::Svg {
+        return Ok(svg::render(&output));
+    }
+    String::from_utf8(output).context("output isn't valid UTF-8")
+}
+
+/// Does everything the `recolor` binary does with `args`: reads the config
+/// file and any styles on stdin, then colors stdin or the input files to
+/// stdout. Returns the status to exit with.
+pub fn cli(mut args: Args) -> Result<ExitCode> {
+    if !args.no_config {
+        if let Some(dir) = dirs::config_dir() {
+            args.config = load_config(&dir.join("recolor").join("config.toml"))?;
+        }
+    }
+    if args.styles_stdin {
+        read_styles(stdin().lock(), &mut args.styles)?;
+    }
+    if args.color == ColorChoice::Auto {
+        // An SVG shows the colors wherever it's written to.
+        args.color = if stdout().is_terminal() || args.output == OutputFormat::Svg {
+            ColorChoice::Always
+        } else {
+            ColorChoice::Never
+        };
+    }
+    if args.colors.is_none() && !args.ascii_only && stdout().is_terminal() {
+        let colorterm = std::env::var("COLORTERM").ok();
+        let term = std::env::var("TERM").ok();
+        args.colors = Some(ansi::ColorDepth::detect(
+            colorterm.as_deref(),
+            term.as_deref(),
+        ));
+    }
+    if args.buffer_size == Some(0) {
+        return Err(anyhow::anyhow!("--buffer-size must be at least 1"));
+    }
+    if args.repeat_style_on_wrap {
+        args.terminal_width = terminal_width().or_else(|| {
+            std::env::var("COLUMNS")
+                .ok()
+                .and_then(|columns| columns.parse().ok())
+        });
+    }
+    debug!("args: {:?}", args);
+
+    if let Some(key) = args.print_escape.clone() {
+        stdout().lock().write_all(&escapes(args, &key)?)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if args.color_test {
+        write_color_test(
+            &mut stdout().lock(),
+            stdout().is_terminal(),
+            |name| std::env::var(name).ok(),
+            filter_options(&args).depth,
+        )?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    #[cfg(feature = "tui")]
+    if args.tui {
+        tui::run(args)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let matches = if args.quiet {
+        run_inputs(&args, io::sink())?
+    } else if args.output == OutputFormat::Svg {
+        let mut output = Vec::new();
+        let matches = run_inputs(&args, &mut output)?;
+        stdout().lock().write_all(svg::render(&output).as_bytes())?;
+        matches
+    } else if let Some(capacity) = args.buffer_size {
+        run_inputs(&args, BufWriter::with_capacity(capacity, stdout().lock()))?
+    } else {
+        run_inputs(&args, stdout().lock())?
+    };
+
+    let min_matches = match args.min_matches {
+        Some(n) => n,
+        None if args.quiet => 1,
+        None => 0,
+    };
+    Ok(if matches < min_matches {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Returns how many columns wide the terminal on stdout is, if there is one.
+fn terminal_width() -> Option<usize> {
+    terminal_size::terminal_size().map(|(width, _)| width.0 as usize)
+}
+
+/// Writes the report for --color-test: the environment that decides how
+/// colors are written, and the same run of colors at each color depth.
+/// `var` reads environment variables and `depth` is the depth in use.
+fn write_color_test(
+    output: &mut impl Write,
+    is_terminal: bool,
+    var: impl Fn(&str) -> Option<String>,
+    depth: ansi::ColorDepth,
+) -> Result<()> {
+    let name = |depth: ansi::ColorDepth| depth.to_possible_value().unwrap().get_name().to_string();
+    writeln!(
+        output,
+        "stdout is a terminal: {}",
+        if is_terminal { "yes" } else { "no" }
+    )?;
+    for name in ["COLORTERM", "TERM", "NO_COLOR"] {
+        let value = var(name).unwrap_or_else(|| "(not set)".to_string());
+        writeln!(output, "{}: {}", name, value)?;
+    }
+    let colorterm = var("COLORTERM");
+    let term = var("TERM");
+    let detected = ansi::ColorDepth::detect(colorterm.as_deref(), term.as_deref());
+    writeln!(output, "detected colors: {}", name(detected))?;
+    writeln!(output, "using colors: {}", name(depth))?;
+
+    for depth in ansi::ColorDepth::value_variants() {
+        write!(output, "{:>9}: ", name(*depth))?;
+        let options = ansi::FilterOptions {
+            depth: *depth,
+            ..Default::default()
+        };
+        let mut swatch = ansi::Filter::new(&mut *output, options);
+        // A run of hues all the way around the color wheel.
+        for i in 0..36 {
+            let (sector, t) = (i / 6, (i % 6) as f64 / 6.0);
+            let up = (t * 255.0).round() as u8;
+            let down = 255 - up;
+            let (r, g, b) = match sector {
+                0 => (255, up, 0),
+                1 => (down, 255, 0),
+                2 => (0, 255, up),
+                3 => (0, down, 255),
+                4 => (up, 0, 255),
+                _ => (255, 0, down),
+            };
+            write!(swatch, "\x1b[48;2;{};{};{}m \x1b[0m", r, g, b)?;
+        }
+        swatch.flush()?;
+        writeln!(output)?;
+    }
+    Ok(())
+}
+
+/// Returns what --print-escape prints for `key`: the sequences that turn its
+/// style on and off. Styles that depend on what a group matched use their
+/// default style.
+fn escapes(mut args: Args, key: &str) -> Result<Vec<u8>> {
+    // There's no regex to match, so the first argument is a style too.
+    if let Some(style) = args.regex.take() {
+        args.styles.insert(0, style);
+    }
+    expand_styles(&mut args)?;
+    let options = filter_options(&args);
+    let styles = parse_styles(args.styles)?;
+    let style = styles
+        .get(key)
+        .context(format!("no style for \"{}\"", key))?
+        .resolve(None, 0);
+
+    let mut escapes = Vec::new();
+    let mut output = ansi::Filter::new(&mut escapes, options);
+    writeln!(output, "{}", Prefix(style))?;
+    writeln!(output, "{}", Suffix(style))?;
+    output.flush()?;
+    drop(output);
+    if args.escaped {
+        let text = String::from_utf8_lossy(&escapes).replace('\x1b', "\\e");
+        escapes = text.into_bytes();
+    }
+    Ok(escapes)
+}
+
+/// How many bytes of input are read at a time without --buffer-size, the same
+/// as `BufReader::new`.
+const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Runs over stdin, or each input file in turn if there are any, returning
+/// the total number of matches.
+fn run_inputs(args: &Args, output: impl Write) -> Result<usize> {
+    let capacity = args.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE);
+    if args.input.is_empty() {
+        let input = BufReader::with_capacity(capacity, stdin().lock());
+        return run(input, output, args.clone());
+    }
+
+    // Each file is only opened once the ones before it are done with.
+    let inputs = args.input.iter().map(|path| {
+        let file = File::open(path).context(format!("failed to open {}", path.display()))?;
+        Ok(Input {
+            name: Some(path.display().to_string()),
+            reader: Box::new(BufReader::with_capacity(capacity, file)),
+        })
+    });
+    run_all(inputs, output, args.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use owo_colors::{OwoColorize, Style};
+    use std::io::Cursor;
+    use test_case::test_case;
+
+    /// A file in the temp directory for a test to read, which is deleted when
+    /// it's dropped, even if the test fails.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &str) -> Result<Self> {
+            let name = format!("recolor-{}-{}", std::process::id(), name);
+            let path = std::env::temp_dir().join(name);
+            std::fs::write(&path, contents)?;
+            Ok(TempFile(path))
+        }
+
+        fn path(&self) -> &std::ffi::OsStr {
+            self.0.as_os_str()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test_case(
+        vec!["(foo)"],
+        "hello foo",
+        format!("hello {}\n", "foo".style(DEFAULT_STYLES[1].owo))
+        ; "single match")
+    ]
+    #[test_case(
+        vec!["(foo)(bar)"],
+        "hello foobar",
+        format!("hello {}{}\n", "foo".style(DEFAULT_STYLES[1].owo), "bar".style(DEFAULT_STYLES[2].owo))
+        ; "multiple match")
+    ]
+    #[test_case(
+        vec!["(?P<foo>foo)(?P<bar>bar)", "foo=green", "bar=red"],
+        "hello foobar",
+        format!(
+            "hello {}{}\n",
+            "foo".style(Style::new().green()),
+            "bar".style(Style::new().red())
+        )
+        ; "named matches")
+    ]
+    #[test_case(
+        vec!["(5)"],
+        "12345 12345 12345",
+        format!(
+            "1234{0} 1234{0} 1234{0}\n",
+            "5".style(DEFAULT_STYLES[1].owo),
+        )
+        ; "multiple single match")
+    ]
+    #[test_case(
+        vec!["(5)"],
+        "hello world",
+        "hello world\n"
+        ; "no matches")
+    ]
+    #[test_case(
+        vec!["(?P<five>5)", "five=#ff0000,underline"],
+        "12345 12345 12345",
+        format!(
+            "1234{0} 1234{0} 1234{0}\n",
+            "5".style(Style::new().truecolor(255, 0, 0).underline()),
+        )
+        ; "CSS colors")
+    ]
+    #[test_case(
+        vec!["123(5)"],
+        "12345 12345 1235",
+        format!(
+            "12345 12345 123{0}\n",
+            "5".style(DEFAULT_STYLES[1].owo),
+        )
+        ; "regex with non-capture group component")
+    ]
+    #[test_case(
+        vec!["12(3(5))"],
+        "12345 12345 1235",
+        format!(
+            "12345 12345 12{}{}\n",
+            "3".style(DEFAULT_STYLES[1].owo),
+            "5".style(DEFAULT_STYLES[2].owo),
+        )
+        ; "capture group inside another capture group")
+    ]
+    #[test_case(
+        vec!["(done)", "--timestamp-relative"],
+        "12:00:00.000 start\n12:00:00.250 working\nno timestamp\n12:00:01,5 done",
+        format!(
+            "{} start\n{} working\nno timestamp\n{} {}\n",
+            "+0.000s".style(Style::new().cyan()),
+            "+0.250s".style(Style::new().cyan()),
+            "+1.250s".style(Style::new().cyan()),
+            "done".style(DEFAULT_STYLES[1].owo),
+        )
+        ; "relative timestamps")
+    ]
+    #[test_case(
+        vec!["(x)", "--timestamp-relative"],
+        "23:59:59 a\n00:00:01 b",
+        format!(
+            "{0} a\n{1} b\n",
+            "+0.000s".style(Style::new().cyan()),
+            "+2.000s".style(Style::new().cyan()),
+        )
+        ; "relative timestamps across midnight")
+    ]
+    #[test_case(
+        vec!["-x", "(?P<year> \\d{4} ) - # the year\n (?P<month> \\d{2} )", "year=red", "month=blue"],
+        "on 2023-07 we",
+        format!(
+            "on {}-{} we\n",
+            "2023".style(Style::new().red()),
+            "07".style(Style::new().blue()),
+        )
+        ; "extended regex")
+    ]
+    #[test_case(
+        vec!["(?P<year>\\d{4})-(?P<month>\\d{2})", "year=red", "month=blue"],
+        "on 2023-07 we",
+        format!(
+            "on {}-{} we\n",
+            "2023".style(Style::new().red()),
+            "07".style(Style::new().blue()),
+        )
+        ; "compact form of extended regex")
+    ]
+    #[test_case(
+        vec!["(x)", "--between", "BEGIN", "END", "blue"],
+        "a BEGIN b END c BEGIN d",
+        format!(
+            "a BEGIN{}END c BEGIN d\n",
+            " b ".style(Style::new().blue()),
+        )
+        ; "between markers")
+    ]
+    #[test_case(
+        vec!["(b)", "--between", "<", ">", "blue"],
+        "<abc> <b>",
+        format!(
+            "<{}{}{}> <{}>\n",
+            "a".style(Style::new().blue()),
+            "b".style(DEFAULT_STYLES[1].owo),
+            "c".style(Style::new().blue()),
+            "b".style(DEFAULT_STYLES[1].owo),
+        )
+        ; "between markers with a group inside")
+    ]
+    #[test_case(
+        vec![r"(?P<status>\d{3})", "status=match(200=green,404=bold,yellow,*=dimmed)"],
+        "200 404 500",
+        format!(
+            "{} {} {}\n",
+            "200".style(Style::new().green()),
+            "404".style(Style::new().bold().yellow()),
+            "500".style(Style::new().dimmed()),
+        )
+        ; "match style")
+    ]
+    #[test_case(
+        vec![r"(?P<status>\d{3})", "status=match(200=green)"],
+        "200 500",
+        format!("{} 500\n", "200".style(Style::new().green()))
+        ; "match style without default")
+    ]
+    #[test_case(
+        vec!["--word", "foo=red", "--word", "a.b=blue"],
+        "foo food a.b axb",
+        format!(
+            "{} food {} axb\n",
+            "foo".style(Style::new().red()),
+            "a.b".style(Style::new().blue()),
+        )
+        ; "words")
+    ]
+    #[test_case(
+        vec!["-i", "--word", "foo=red"],
+        "Foo FOO",
+        format!(
+            "{} {}\n",
+            "Foo".style(Style::new().red()),
+            "FOO".style(Style::new().red()),
+        )
+        ; "case-insensitive words")
+    ]
+    #[test_case(
+        vec!["--word", "c++=red", "--word", ".NET=blue", "--word", "go=bold"],
+        "c++ .NET go gopher",
+        format!(
+            "{} {} {} gopher\n",
+            "c++".style(Style::new().red()),
+            ".NET".style(Style::new().blue()),
+            "go".style(Style::new().bold()),
+        )
+        ; "words starting or ending with punctuation")
+    ]
+    #[test_case(
+        vec!["--word", "c++=red", "--word", "go=bold", "--dry-run", "--legend"],
+        "c++ go",
+        "«c++:c++» «go:go»\n«c++:c++» «go:go»\n"
+        ; "words are labelled with themselves")
+    ]
+    #[test_case(
+        vec!["(a)", "--squeeze-blank"],
+        "a\n\n\n\nb\n\nc\n",
+        format!("{}\n\nb\n\nc\n", "a".style(DEFAULT_STYLES[1].owo))
+        ; "squeeze blank lines")
+    ]
+    #[test_case(
+        vec!["(?P<secret>s3cr3t)", "secret=conceal"],
+        "password: s3cr3t",
+        "password: \x1b[8ms3cr3t\x1b[0m\n"
+        ; "conceal")
+    ]
+    #[test_case(
+        vec!["(?P<quiet>debug)", "quiet=dim"],
+        "debug info",
+        "\x1b[2mdebug\x1b[0m info\n"
+        ; "dim")
+    ]
+    #[test_case(
+        vec!["(?P<quiet>debug)(?P<loud>info)", "quiet=faint", "loud=bold,faint"],
+        "debuginfo",
+        "\x1b[2mdebug\x1b[0m\x1b[1;2minfo\x1b[0m\n"
+        ; "faint and bold")
+    ]
+    #[test_case(
+        vec!["(?P<word>f(o)o)(bar)?", "--dry-run"],
+        "a foo foobar",
+        "a «word:f«2:o»o» «word:f«2:o»o»«3:bar»\n"
+        ; "dry run")
+    ]
+    #[test_case(
+        vec!["(?P<a>a)(?P<b>b)", "a=rgb(255, 0, 128),bold", "b=rgb(100%,0%,50%)"],
+        "ab",
+        format!(
+            "{}{}\n",
+            "a".style(Style::new().truecolor(255, 0, 128).bold()),
+            "b".style(Style::new().truecolor(255, 0, 128)),
+        )
+        ; "rgb colors")
+    ]
+    #[test_case(
+        vec!["(a)(?P<b>b)(c)", "1=blue", "2=bold", "b=red"],
+        "abc",
+        format!(
+            "{}{}{}\n",
+            "a".style(Style::new().blue()),
+            "b".style(Style::new().red()),
+            "c".style(DEFAULT_STYLES[3].owo),
+        )
+        ; "numbered groups")
+    ]
+    #[test_case(
+        vec!["(a)(b)", "--reverse-palette"],
+        "ab",
+        format!(
+            "{}{}\n",
+            "a".style(DEFAULT_STYLES[DEFAULT_STYLES.len() - 2].owo),
+            "b".style(DEFAULT_STYLES[DEFAULT_STYLES.len() - 3].owo),
+        )
+        ; "reverse palette")
+    ]
+    #[test_case(
+        vec!["(a)(b)", "--palette-offset", "2"],
+        "ab",
+        format!(
+            "{}{}\n",
+            "a".style(DEFAULT_STYLES[3].owo),
+            "b".style(DEFAULT_STYLES[4].owo),
+        )
+        ; "palette offset")
+    ]
+    #[test_case(
+        vec!["(?P<code>E\\d+)", "--line-style", "on_red", "code=bold"],
+        "error E42 here\nfine",
+        format!(
+            "{}\x1b[41m{}{}\nfine\n",
+            "error ".style(Style::new().on_red()),
+            "E42".style(Style::new().bold()),
+            " here".style(Style::new().on_red()),
+        )
+        ; "line style")
+    ]
+    #[test_case(
+        vec!["(E)", "--line-style", "on_red"],
+        "E!",
+        format!(
+            "\x1b[41m{}{}\n",
+            "E".style(DEFAULT_STYLES[1].owo),
+            "!".style(Style::new().on_red()),
+        )
+        ; "line style with match at start")
+    ]
+    #[test_case(
+        vec!["(?P<a>a)", "a=on_#00ff00,on_blue,red"],
+        "a",
+        format!("{}\n", "a".style(Style::new().on_blue().red()))
+        ; "background colors")
+    ]
+    #[test_case(
+        vec!["(b)", "--prefix", "> ", "--prefix-style", "bold", "--suffix", " <"],
+        "abc",
+        format!(
+            "{}a{}c <\n",
+            "> ".style(Style::new().bold()),
+            "b".style(DEFAULT_STYLES[1].owo),
+        )
+        ; "prefix and suffix")
+    ]
+    #[test_case(
+        vec!["(b)", "-H"],
+        "abc",
+        format!(
+            "{}:a{}c\n",
+            "(standard input)".style(Style::new().magenta()),
+            "b".style(DEFAULT_STYLES[1].owo),
+        )
+        ; "with filename")
+    ]
+    #[test_case(
+        vec!["(?P<bar>a+)", "bar=bg_gradient(#000,#0000ff),bold"],
+        "[aaa]",
+        format!(
+            "[{}{}{}]\n",
+            "a".style(Style::new().bold().on_truecolor(0, 0, 0)),
+            "a".style(Style::new().bold().on_truecolor(0, 0, 128)),
+            "a".style(Style::new().bold().on_truecolor(0, 0, 255)),
+        )
+        ; "background gradient")
+    ]
+    #[test_case(
+        vec!["(?P<bar>a+)", "bar=bg_gradient(rgb(0,0,0),rgb(0, 0, 255))"],
+        "[aa]",
+        format!(
+            "[{}{}]\n",
+            "a".style(Style::new().on_truecolor(0, 0, 0)),
+            "a".style(Style::new().on_truecolor(0, 0, 255)),
+        )
+        ; "background gradient with rgb colors")
+    ]
+    #[test_case(
+        vec!["(?P<bar>a+)", "bar=bg_gradient(#000,#00f)", "--color", "never"],
+        "[aaa]",
+        "[aaa]\n"
+        ; "background gradient without color")
+    ]
+    #[test_case(
+        vec!["(?P<bar>a+)", "bar=bg_gradient(#000,#00f)", "--dry-run"],
+        "[aaa]",
+        "[«bar:aaa»]\n"
+        ; "background gradient dry run")
+    ]
+    #[test_case(
+        vec!["(c+)", "--max-line-length", "4"],
+        "abccc\nabc\n",
+        format!(
+            "ab{}…\nab{}\n",
+            "cc".style(DEFAULT_STYLES[1].owo),
+            "c".style(DEFAULT_STYLES[1].owo),
+        )
+        ; "max line length")
+    ]
+    #[test_case(
+        vec!["(b.*)", "1=red", "--max-line-length", "3", "--line-style", "underline"],
+        "abcdef\n",
+        "\x1b[4ma\x1b[0m\x1b[4m\x1b[31mbc\x1b[0m…\n"
+        ; "max line length closes styles before the ellipsis")
+    ]
+    #[test_case(
+        vec!["(r)", "1=blue", "--max-line-length", "3"],
+        "\x1b[31mredred\x1b[0m\nabcd",
+        "\x1b[31m\x1b[34mr\x1b[0med\x1b[0m…\nabc…\n"
+        ; "max line length only counts text")
+    ]
+    #[test_case(
+        vec!["(b)", "--tmux-passthrough"],
+        "abc",
+        "a\x1bPtmux;\x1b\x1b[32m\x1b\\b\x1bPtmux;\x1b\x1b[0m\x1b\\c\n"
+        ; "tmux passthrough")
+    ]
+    #[test_case(
+        vec!["(a)(?P<name>b)", "name=red", "--legend"],
+        "ab",
+        format!(
+            "{} {}\n{}{}\n",
+            "1".style(Style::new().green()),
+            "name".style(Style::new().red()),
+            "a".style(Style::new().green()),
+            "b".style(Style::new().red())
+        )
+        ; "legend")
+    ]
+    #[test_case(
+        vec!["(b)", "--reset-on-start"],
+        "abc\nb",
+        format!("\x1b[0ma{}c\n{}\n", "b".style(DEFAULT_STYLES[1].owo), "b".style(DEFAULT_STYLES[1].owo))
+        ; "reset on start")
+    ]
+    #[test_case(
+        vec!["(b)", "--reset-on-start", "--color", "never"],
+        "abc",
+        "abc\n"
+        ; "reset on start without color")
+    ]
+    #[test_case(
+        vec!["12(3(5))"],
+        "1235",
+        format!("12{}{}\n", "3".style(DEFAULT_STYLES[1].owo), "5".style(DEFAULT_STYLES[2].owo))
+        ; "style mode innermost")
+    ]
+    #[test_case(
+        vec!["12(3(5))", "--style-mode", "outermost"],
+        "1235",
+        format!("12{}{}\n", "3".style(DEFAULT_STYLES[1].owo), "5".style(DEFAULT_STYLES[1].owo))
+        ; "style mode outermost")
+    ]
+    #[test_case(
+        vec!["12(3(5))", "--style-mode", "stack"],
+        "1235",
+        format!(
+            "12{}{}{}\n",
+            "3".style(DEFAULT_STYLES[1].owo),
+            Prefix(DEFAULT_STYLES[1]),
+            "5".style(DEFAULT_STYLES[2].owo)
+        )
+        ; "style mode stack")
+    ]
+    #[test_case(
+        vec!["(b c)", "--highlight-whitespace"],
+        "a b c\td",
+        format!(
+            "a{}{}{}{}{}{}d\n",
+            "·".style(Style::new().dimmed()),
+            "b".style(DEFAULT_STYLES[1].owo),
+            Prefix(DEFAULT_STYLES[1]),
+            "·".style(Style::new().dimmed()),
+            "c".style(DEFAULT_STYLES[1].owo),
+            "→".style(Style::new().dimmed()),
+        )
+        ; "highlight whitespace")
+    ]
+    #[test_case(
+        vec!["(b)", "--show-all", "--color", "never"],
+        "a b \n",
+        "a·b·$\n"
+        ; "show all")
+    ]
+    #[test_case(
+        vec![
+            r"(?P<level>\w+) (?P<message>.*)",
+            "level=bold",
+            "message=match@level(ERROR=red,*=dimmed)",
+        ],
+        "ERROR disk full\nINFO ok",
+        format!(
+            "{} {}\n{} {}\n",
+            "ERROR".style(Style::new().bold()),
+            "disk full".style(Style::new().red()),
+            "INFO".style(Style::new().bold()),
+            "ok".style(Style::new().dimmed())
+        )
+        ; "match style from another group")
+    ]
+    #[test_case(
+        vec!["^(a)", "--trim", "-s"],
+        "  a  \n \n\t\n\ta",
+        format!("{}\n\n{}\n", "a".style(DEFAULT_STYLES[1].owo), "a".style(DEFAULT_STYLES[1].owo))
+        ; "trim")
+    ]
+    #[test_case(
+        vec!["(a+)", "--columns", "4:6"],
+        "aa aaa aa",
+        format!("aa {} aa\n", "aaa".style(DEFAULT_STYLES[1].owo))
+        ; "columns")
+    ]
+    #[test_case(
+        vec!["(a)", "--columns", "2:"],
+        "aba",
+        format!("ab{}\n", "a".style(DEFAULT_STYLES[1].owo))
+        ; "columns without end")
+    ]
+    #[test_case(
+        vec!["(a)(b)(c)", "--default-palette", "#f00, rgb(0,0,255)"],
+        "abc",
+        format!(
+            "{}{}{}\n",
+            "a".style(Style::new().truecolor(0, 0, 255)),
+            "b".style(Style::new().truecolor(255, 0, 0)),
+            "c".style(Style::new().truecolor(0, 0, 255))
+        )
+        ; "default palette")
+    ]
+    #[test_case(
+        vec!["(a)(?P<b>b)(c)", "b=red", "--no-default-color"],
+        "abc",
+        format!("a{}c\n", "b".style(Style::new().red()))
+        ; "no default color")
+    ]
+    #[test_case(
+        vec!["(b)", "--line-range", "2:3=bold", "--line-range", "3:=dimmed"],
+        "a\na\na\na\n",
+        format!(
+            "a\n{}\n{}\n{}\n",
+            "a".style(Style::new().bold()),
+            "a".style(Style::new().dimmed()),
+            "a".style(Style::new().dimmed())
+        )
+        ; "line ranges")
+    ]
+    #[test_case(
+        vec![r"(\d+)", "--only-when", "DENY"],
+        "ALLOW 1\nDENY 2",
+        format!("ALLOW 1\nDENY {}\n", "2".style(DEFAULT_STYLES[1].owo))
+        ; "only when")
+    ]
+    #[test_case(
+        vec![r"(?P<user>\w+)@(\w+)", "--replace", "$$${user} at $2 ($0)"],
+        "mail bob@host now",
+        format!(
+            "mail ${} at {} (bob@host) now\n",
+            "bob".style(DEFAULT_STYLES[1].owo),
+            "host".style(DEFAULT_STYLES[2].owo)
+        )
+        ; "replace")
+    ]
+    #[test_case(
+        vec![r"(?P<n>\d)", "n=red|green|blue"],
+        "1 2 3 4",
+        format!(
+            "{} {} {} {}\n",
+            "1".style(Style::new().red()),
+            "2".style(Style::new().green()),
+            "3".style(Style::new().blue()),
+            "4".style(Style::new().red())
+        )
+        ; "cycling styles")
+    ]
+    #[test_case(
+        vec!["(a)(b)(c)", "1=#ff1010", "2=white,on_rgb(0,0,200),bold", "3=red", "--ascii-only"],
+        "abc",
+        "\x1b[91ma\x1b[0m\x1b[37;44;1mb\x1b[0m\x1b[31mc\x1b[0m\n"
+        ; "ascii only")
+    ]
+    #[test_case(
+        vec!["(a)(b)(c)", "1=#ff1010", "2=white,on_ansi(4)", "3=rgb(128,128,128)", "--colors", "256"],
+        "abc",
+        "\x1b[38;5;196ma\x1b[0m\x1b[37;44mb\x1b[0m\x1b[38;5;244mc\x1b[0m\n"
+        ; "256 colors")
+    ]
+    #[test_case(
+        vec!["(x)", "--highlight-duplicates", "--duplicate-style", "bold", "--no-default-color"],
+        "a\nb\na\na",
+        "a\nb\n\x1b[1ma\x1b[0m\n\x1b[1ma\x1b[0m\n"
+        ; "highlight duplicates")
+    ]
+    #[test_case(
+        vec!["(x)", "--highlight-duplicates", "--consecutive-only", "--duplicate-style", "bold"],
+        "a\nb\na\na",
+        "a\nb\na\n\x1b[1ma\x1b[0m\n"
+        ; "highlight consecutive duplicates")
+    ]
+    #[test_case(
+        vec!["(\\w+) (\\w+)", "1=rpad(5)", "2=red,pad(4)"],
+        "ab cd\nabcdef c",
+        "ab      \x1b[31mcd\x1b[0m\nabcdef    \x1b[31mc\x1b[0m\n"
+        ; "padding")
+    ]
+    #[test_case(
+        vec!["(\\w+)", "1=rpad(3)", "--truncate-padded"],
+        "abcdef ab",
+        "abc ab \n"
+        ; "truncated padding")
+    ]
+    #[test_case(
+        vec!["(b)", "--no-trailing-newline"],
+        "ab\nb\n",
+        "a\x1b[32mb\x1b[0m\n\x1b[32mb\x1b[0m"
+        ; "no trailing newline")
+    ]
+    #[test_case(
+        vec!["(?P<a>b\\nc)", "a=red", "--window", "2"],
+        "ab\nc b\nc\n",
+        "a\x1b[31mb\x1b[0m\n\x1b[31mc\x1b[0m \x1b[31mb\x1b[0m\n\x1b[31mc\x1b[0m\n"
+        ; "window")
+    ]
+    #[test_case(
+        vec!["(?P<a>1(?:\\n\\d)*)", "a=red", "--window", "2"],
+        "1\n1\n1\n",
+        "\x1b[31m1\x1b[0m\n\x1b[31m1\x1b[0m\n\x1b[31m1\x1b[0m\n"
+        ; "window skips overlapping matches")
+    ]
+    #[test_case(
+        vec!["(?P<a>a\\nb)(?P<c>\\nc)", "a=red,first_line_only", "c=blue", "--window", "3"],
+        "xa\nb\nc\n",
+        "x\x1b[31ma\x1b[0m\nb\n\x1b[34mc\x1b[0m\n"
+        ; "first line only")
+    ]
+    #[test_case(
+        vec!["(?P<a>a\\nb)(\\nc)", "a=red", "--window", "3", "--first-line-only"],
+        "xa\nb\nc\n",
+        "x\x1b[31ma\x1b[0m\nb\nc\n"
+        ; "first line only for every group")
+    ]
+    #[test_case(
+        vec!["(\\d+)-(\\d+)", "1=red", "2=blue", "-o"],
+        "a 1-2 b 33-4\nnone\n5-6",
+        "\x1b[31m1\x1b[0m-\x1b[34m2\x1b[0m\n\x1b[31m33\x1b[0m-\x1b[34m4\x1b[0m\n\x1b[31m5\x1b[0m-\x1b[34m6\x1b[0m\n"
+        ; "only matching")
+    ]
+    #[test_case(
+        vec!["\\d+", "-o", "--group-separator", "\\t"],
+        "a 1 b 22\n3",
+        "1\t22\t3\t"
+        ; "only matching with a tab separator")
+    ]
+    #[test_case(
+        vec!["(a)(b)(c)", "1=framed", "2=red,encircled", "3=framed,encircled,bold"],
+        "abc",
+        "\x1b[51ma\x1b[0m\x1b[31m\x1b[52mb\x1b[0m\x1b[1m\x1b[51m\x1b[52mc\x1b[0m\n"
+        ; "framed and encircled")
+    ]
+    #[test_case(
+        vec!["(\\w+): (.*)", "--normalize-case", "upper"],
+        "warn: disk Straße full",
+        format!(
+            "{}: {}\n",
+            "WARN".style(DEFAULT_STYLES[1].owo),
+            "DISK STRASSE FULL".style(DEFAULT_STYLES[2].owo)
+        )
+        ; "normalize case upper")
+    ]
+    #[test_case(
+        vec!["(\\w+): (.*)", "--normalize-case", "title", "--no-default-color"],
+        "WARN: disk-space FULL",
+        "Warn: Disk-Space Full\n"
+        ; "normalize case title")
+    ]
+    #[test_case(
+        vec!["(?P<size>\\w+) (?P<name>\\w+)", "size=red,humanize(bytes)", "name=blue"],
+        "1048576 a\n512 b\nbig c",
+        "\x1b[31m1.0 MiB\x1b[0m \x1b[34ma\x1b[0m\n\x1b[31m512 B\x1b[0m \x1b[34mb\x1b[0m\n\x1b[31mbig\x1b[0m \x1b[34mc\x1b[0m\n"
+        ; "humanize bytes")
+    ]
+    #[test_case(
+        vec!["(?P<size>[\\d.]+)", "size=humanize(bytes)"],
+        "1023.4 1023.7 1536.2",
+        "1023 B 1.0 KiB 1.5 KiB\n"
+        ; "humanize fractional bytes")
+    ]
+    #[test_case(
+        vec!["took (?P<t>(\\d)[\\d.]*m?s)", "t=humanize(duration),rpad(9)", "2=red"],
+        "took 3725s, took 0.25s, took 1500ms",
+        "took \x1b[31m1h 2m 5s\x1b[0m , took \x1b[31m250ms\x1b[0m    , took \x1b[31m1.5s\x1b[0m     \n"
+        ; "humanize duration")
+    ]
+    #[test_case(
+        vec![r"(?P<level>\w+): (\w+)", "--replace", "$[match@level(ERROR=red,*=blue)]{[!]} ${2:bold} ${0:dimmed}", "--no-default-color"],
+        "ERROR: a\nINFO: b",
+        "\x1b[31m[!]\x1b[0m \x1b[1ma\x1b[0m \x1b[2mERROR: a\x1b[0m\n\x1b[34m[!]\x1b[0m \x1b[1mb\x1b[0m \x1b[2mINFO: b\x1b[0m\n"
+        ; "styled template")
+    ]
+    #[test_case(
+        vec!["--urls"],
+        "see https://example.com/a?b=1. (or ftp://x.org)",
+        "see \x1b]8;;https://example.com/a?b=1\x1b\\\x1b[34;4mhttps://example.com/a?b=1\x1b[0m\x1b]8;;\x1b\\. (or \x1b]8;;ftp://x.org\x1b\\\x1b[34;4mftp://x.org\x1b[0m\x1b]8;;\x1b\\)\n"
+        ; "urls")
+    ]
+    #[test_case(
+        vec!["--urls", "red", "--replace", "<$url>"],
+        "at http://a.b now",
+        "at \x1b]8;;http://a.b\x1b\\<\x1b[31mhttp://a.b\x1b[0m>\x1b]8;;\x1b\\ now\n"
+        ; "urls with a template")
+    ]
+    #[test_case(
+        vec![r"(?P<key>\w+)(=|:)(\w+)", "--named-only"],
+        "a=1 b:2",
+        format!(
+            "{}=1 {}:2\n",
+            "a".style(DEFAULT_STYLES[1].owo),
+            "b".style(DEFAULT_STYLES[1].owo)
+        )
+        ; "named only")
+    ]
+    #[test_case(
+        vec!["(x)", "1=red", "--fade", "2"],
+        "a\nx\nb\nc\nd",
+        "\x1b[38;5;238ma\x1b[0m\n\x1b[31mx\x1b[0m\n\x1b[38;5;245mb\x1b[0m\n\x1b[38;5;238mc\x1b[0m\n\x1b[38;5;238md\x1b[0m\n"
+        ; "fade")
+    ]
+    #[test_case(
+        vec![r"(\w+)=(\d+)", "1=red", "2=blue", "--ignore-ansi-in-matching"],
+        "\x1b[33mkey\x1b[0m=\x1b[36m4\x1b[1m2\x1b[0m tail",
+        "\x1b[33m\x1b[31mkey\x1b[0m\x1b[33m\x1b[0m=\x1b[36m\x1b[34m4\x1b[0m\x1b[36m\x1b[1m\x1b[34m2\x1b[0m\x1b[36m\x1b[1m\x1b[0m tail\n"
+        ; "ignore ansi in matching")
+    ]
+    #[test_case(
+        vec!["(error)", "1=blue", "--ignore-ansi-in-matching", "--timestamp-relative"],
+        "2024-01-01T00:00:00Z \x1b[31mred\x1b[0m (error)",
+        "\x1b[36m+0.000s\x1b[0m \x1b[31mred\x1b[0m (\x1b[34merror\x1b[0m)\n"
+        ; "ignore ansi in matching with relative timestamps")
+    ]
+    #[test_case(
+        vec!["(r)", "1=blue", "--ignore-ansi-in-matching", "--max-line-length", "2"],
+        "\x1b[31mred\x1b[0m\nplain",
+        "\x1b[31m\x1b[34mr\x1b[0m\x1b[31me\x1b[0m…\npl…\n"
+        ; "ignore ansi in matching with max line length")
+    ]
+    #[test_case(
+        vec![r"(\w+)=(\d+)", "1=red", "2=blue"],
+        "\x1b[33mkey\x1b[0m=\x1b[36m42\x1b[0m",
+        "\x1b[33mkey\x1b[0m=\x1b[36m42\x1b[0m\n"
+        ; "ansi gets in the way of matching")
+    ]
+    #[test_case(
+        vec!["(a)", "1=red", "--max-groups-per-line", "2"],
+        "aaa\na",
+        "\x1b[31ma\x1b[0m\x1b[31ma\x1b[0ma\n\x1b[31ma\x1b[0m\n"
+        ; "max groups per line")
+    ]
+    #[test_case(
+        vec![r"(\S+)", "1=classify(num=cyan,word=bold,punct=red,other=dimmed)"],
+        "x_1 -2.5 => a.b",
+        "\x1b[1mx_1\x1b[0m \x1b[36m-2.5\x1b[0m \x1b[31m=>\x1b[0m \x1b[2ma.b\x1b[0m\n"
+        ; "classify")
+    ]
+    #[test_case(
+        vec!["(b)", "1=red", "--profile"],
+        "abc",
+        "a\x1b[31mb\x1b[0mc\n"
+        ; "profile")
+    ]
+    #[test_case(
+        vec![r"(\w)(\w)", "1=red", "2=red,bold", "--compact-escapes"],
+        "ab cd",
+        "\x1b[31ma\x1b[1mb\x1b[0m \x1b[31mc\x1b[1md\x1b[0m\n"
+        ; "compact escapes")
+    ]
+    #[test_case(
+        vec!["(h)i", "1=red", "--reset-on-start", "--compact-escapes"],
+        "hi",
+        "\x1b[0m\x1b[31mh\x1b[0mi\n"
+        ; "compact escapes after a reset on start")
+    ]
+    #[test_case(
+        vec!["(e|👍)", "1=red", "--whole-graphemes"],
+        "ne\u{301}e 👍🏽!",
+        "n\x1b[31me\u{301}\x1b[0m\x1b[31me\x1b[0m \x1b[31m👍🏽\x1b[0m!\n"
+        ; "whole graphemes")
+    ]
+    #[test_case(
+        vec!["(e|👍)", "1=red"],
+        "ne\u{301} 👍🏽",
+        "n\x1b[31me\x1b[0m\u{301} \x1b[31m👍\x1b[0m🏽\n"
+        ; "split graphemes")
+    ]
+    #[test_case(
+        vec![r"(👩|\x{1F1EC}|👋)", "1=red", "--whole-graphemes"],
+        "👩\u{200d}💻 🇬🇧 👋🏿",
+        "\x1b[31m👩\u{200d}💻\x1b[0m \x1b[31m🇬🇧\x1b[0m \x1b[31m👋🏿\x1b[0m\n"
+        ; "whole emoji graphemes")
+    ]
+    #[test_case(
+        vec!["(b)", "1=red", "--tac"],
+        "a\nb\n\nc",
+        "c\n\n\x1b[31mb\x1b[0m\na\n"
+        ; "reverse lines")
+    ]
+    #[test_case(
+        vec!["a", "--tac", "--replace", "x\ny", "--no-default-color"],
+        "a\nb\n",
+        "b\nx\ny\n"
+        ; "reverse lines with a template that adds lines")
+    ]
+    #[test_case(
+        vec!["(a)", "--tac", "--legend", "--reset-on-start", "--no-default-color"],
+        "a\nb\n",
+        "\x1b[0m1\nb\na\n"
+        ; "reverse lines with a legend")
+    ]
+    #[test_case(
+        vec!["(a)(b)?", "1=red", "2=red", "--merge-adjacent-same-style"],
+        "aabxa ba",
+        "\x1b[31maab\x1b[0mx\x1b[31ma\x1b[0m b\x1b[31ma\x1b[0m\n"
+        ; "merge adjacent same style")
+    ]
+    #[test_case(
+        vec!["(a)|(?P<n>\\d)", "--delimit-matches", "--group-delimiters", "n=[,]", "--color", "never"],
+        "a 1 b",
+        "<<a>> [1] b\n"
+        ; "delimit matches")
+    ]
+    #[test_case(
+        vec!["(a)", "1=red", "--delimit-matches={,}"],
+        "xay",
+        "x{\x1b[31ma\x1b[0m}y\n"
+        ; "delimit styled matches")
+    ]
+    #[test_case(
+        vec!["(a+)", "1=red", "--collapse-unmatched"],
+        "xaa y a\nnone\naz",
+        "…\x1b[31maa\x1b[0m…\x1b[31ma\x1b[0m\nnone\n\x1b[31ma\x1b[0m…\n"
+        ; "collapse unmatched")
+    ]
+    #[test_case(
+        vec!["(a)(b)?", "--collapse-unmatched=...", "--drop-unmatched-lines", "--color", "never"],
+        "ab a\nnone\nxa",
+        "ab...a\n...a\n"
+        ; "collapse unmatched and drop unmatched lines")
+    ]
+    #[test_case(
+        vec!["(\\p{Hebrew}+)", "1=red", "--bidi-isolate"],
+        "id שלום: 1",
+        "id \u{2068}\x1b[31mשלום\x1b[0m\u{2069}: 1\n"
+        ; "bidi isolate")
+    ]
+    #[test_case(
+        vec!["(a)", "--checksum"],
+        "123456789\na",
+        "\x1b[2mcbf43926\x1b[0m 123456789\n\x1b[2me8b7be43\x1b[0m \x1b[32ma\x1b[0m\n"
+        ; "checksum")
+    ]
+    #[test_case(
+        vec!["(a)", "--checksum=fnv1a", "--checksum-position", "end", "--color", "never"],
+        "a",
+        "a e40c292c\n"
+        ; "checksum at end")
+    ]
+    #[test_case(
+        vec!["(a)", "--columns", "9:", "--tab-stop", "8", "--color", "never", "--dry-run"],
+        "a\ta\n  a\ta",
+        "a\t«1:a»\n  a\t«1:a»\n"
+        ; "columns with tab stops")
+    ]
+    #[test_case(
+        vec!["(?P<ms>\\d+|-)ms", "ms=heat(0,100,#0000ff,#ff0000),bold"],
+        "0ms 50ms 500ms -ms",
+        concat!(
+            "\x1b[38;2;0;0;255;1m0\x1b[0mms ",
+            "\x1b[38;2;128;0;128;1m50\x1b[0mms ",
+            "\x1b[38;2;255;0;0;1m500\x1b[0mms ",
+            "\x1b[1m-\x1b[0mms\n"
+        )
+        ; "heat")
+    ]
+    #[test_case(
+        vec!["(\\d+)", "1=heat(0,1000,rgb(0,0,255),#f00)"],
+        "0 1000",
+        "\x1b[38;2;0;0;255m0\x1b[0m \x1b[38;2;255;0;0m1000\x1b[0m\n"
+        ; "heat with rgb colors")
+    ]
+    #[test_case(
+        vec!["(?P<n>\\d+)", "n=humanize(bytes)", "--replace", "[$n]", "--no-default-color"],
+        "2048",
+        "[2.0 KiB]\n"
+        ; "humanize with a template")
+    ]
+    #[test_case(
+        vec!["(\\w+)(?:=(\\d+))?", "1=red", "2=blue", "--require-all-groups"],
+        "a=1 b",
+        "\x1b[31ma\x1b[0m=\x1b[34m1\x1b[0m b\n"
+        ; "require all groups")
+    ]
+    #[test_case(
+        vec!["(b+)", "1=blue", "--max-width", "3", "--max-width-style", "bold"],
+        "abbbbc\nabc\nabcd",
+        "a\x1b[34mbb\x1b[0m\x1b[1m\x1b[34mbb\x1b[0m\x1b[1mc\x1b[0m\na\x1b[34mb\x1b[0mc\na\x1b[34mb\x1b[0mc\x1b[1md\x1b[0m\n"
+        ; "max width")
+    ]
+    #[test_case(
+        vec!["(b)", "1=blue", "--animate", "--fps", "1000"],
+        "ab\nbc",
+        "a\x1b[34mb\x1b[0m\r\x1b[2K\x1b[34mb\x1b[0mc\n"
+        ; "animate")
+    ]
+    #[test_case(
+        vec!["(a)(b)(?P<c>c)(d)", "4=red", "--fallback-style", "dimmed"],
+        "abcd",
+        "\x1b[2ma\x1b[0m\x1b[2mb\x1b[0m\x1b[2mc\x1b[0m\x1b[31md\x1b[0m\n"
+        ; "fallback style")
+    ]
+    #[cfg_attr(
+        unix,
+        test_case(
+            vec!["(?P<level>\\w+): (\\w+)", "--style-cmd", "level", "grep -q E && echo red || echo blue", "--style-cmd", "2", "echo bold"],
+            "ERROR: a\nINFO: b\nERROR: c",
+            "\x1b[31mERROR\x1b[0m: \x1b[1ma\x1b[0m\n\x1b[34mINFO\x1b[0m: \x1b[1mb\x1b[0m\n\x1b[31mERROR\x1b[0m: \x1b[1mc\x1b[0m\n"
+            ; "style commands")
+    )]
+    fn test_success(
+        args: impl Into<Vec<&'static str>>,
+        input: impl Into<String>,
+        expected_output: impl Into<String>,
+    ) -> Result<()> {
+        let mut output = Vec::new();
+        let mut args: Vec<&str> = args.into();
+        args.insert(0, "recolor");
+        let args = Args::parse_from(args);
+        run(Cursor::new(input.into()), &mut output, args)?;
+        assert_eq!(String::from_utf8(output)?, expected_output.into());
+        Ok(())
+    }
+
+    #[test_case(vec![r"(\w+)(=\w+)", "1=red", "2=red,bold"], "a=b c=d" ; "shared colors")]
+    #[test_case(vec![r"((a)(b))", "1=dimmed,blue", "2=bold", "3=on_red", "--style-mode", "stack"], "xaby" ; "stacked")]
+    #[test_case(vec![r"(\d)", "--line-style", "italic"], "1 2\n3\nno" ; "line style")]
+    #[test_case(vec![r"(b)", "1=red"], "\x1b[1ma\x1b[0mbc\x1b[4md" ; "input escapes")]
+    fn test_compact_escapes(args: Vec<&str>, input: &str) -> Result<()> {
+        let render = |extra: &[&'static str]| -> Result<Vec<u8>> {
+            let mut output = Vec::new();
+            let args = Args::parse_from(["recolor"].iter().chain(&args).chain(extra));
+            run(Cursor::new(input.to_string()), &mut output, args)?;
+            Ok(output)
+        };
+        let (full, compact) = (render(&[])?, render(&["--compact-escapes"])?);
+        assert!(compact.len() < full.len());
+        assert_eq!(svg::render(&compact), svg::render(&full));
+        Ok(())
+    }
+
+    #[test_case(
+        ansi::OscMode::Pass,
+        "a\x1b]0;title\x07b\x1b]2;other\x1b\\c",
+        "a\x1b]0;title\x07b\x1b]2;other\x1b\\c"
+    )]
+    #[test_case(ansi::OscMode::Strip, "a\x1b]0;title\x07b\x1b]2;other\x1b\\c", "abc")]
+    #[test_case(ansi::OscMode::Strip, "\x1b[31ma\x1b[0m", "\x1b[31ma\x1b[0m")]
+    fn test_osc(osc: ansi::OscMode, input: &str, expected: &str) -> Result<()> {
+        let mut output = Vec::new();
+        let options = ansi::FilterOptions {
+            osc,
+            ..Default::default()
+        };
+        let mut filter = ansi::Filter::new(&mut output, options);
+        filter.write_all(input.as_bytes())?;
+        filter.flush()?;
+        drop(filter);
+        assert_eq!(String::from_utf8(output)?, expected);
+        Ok(())
+    }
+
+    #[test_case("\x1b[31mabcdef\x1b[0m\nabcde", "\x1b[31mabcd\x1b[31mef\x1b[0m\nabcde" ; "wraps")]
+    #[test_case("ab\x1b[1mcd\x1b[0me", "ab\x1b[1mcd\x1b[0me" ; "reset before wrapping")]
+    #[test_case("\x1b[32méé\x1b[1méé\x1b[0m", "\x1b[32méé\x1b[1méé\x1b[0m" ; "fits")]
+    #[test_case("\x1b[34m\tab", "\x1b[34m\ta\x1b[34mb" ; "tab")]
+    fn test_repeat_style_on_wrap(input: &str, expected: &str) -> Result<()> {
+        let mut output = Vec::new();
+        let options = ansi::FilterOptions {
+            wrap_width: Some(4),
+            ..Default::default()
+        };
+        let mut filter = ansi::Filter::new(&mut output, options);
+        filter.write_all(input.as_bytes())?;
+        filter.flush()?;
+        drop(filter);
+        assert_eq!(String::from_utf8(output)?, expected);
+        Ok(())
+    }
+
+    #[test_case(Some("truecolor"), Some("xterm-256color"), ansi::ColorDepth::TrueColor)]
+    #[test_case(Some("24bit"), Some("xterm"), ansi::ColorDepth::TrueColor)]
+    #[test_case(None, Some("xterm-256color"), ansi::ColorDepth::Ansi256)]
+    #[test_case(None, Some("xterm"), ansi::ColorDepth::Ansi16)]
+    #[test_case(Some("yes"), Some("linux"), ansi::ColorDepth::Ansi16)]
+    #[test_case(None, None, ansi::ColorDepth::TrueColor)]
+    fn test_detect_color_depth(
+        colorterm: Option<&str>,
+        term: Option<&str>,
+        expected: ansi::ColorDepth,
+    ) {
+        assert_eq!(ansi::ColorDepth::detect(colorterm, term), expected);
+    }
+
+    #[test_case(&["1=red,hidden"], true ; "hidden")]
+    #[test_case(&["1=match(x=on_red,*=bold)"], true ; "background in a match")]
+    #[test_case(&["1=red|blinking"], true ; "blink in a cycle")]
+    #[test_case(&["1=match(hidden=red)"], false ; "match value")]
+    #[test_case(&["1=on_red", "--unsafe-attributes", "hidden"], false ; "allowed attribute")]
+    #[test_case(&["--line-style", "bold,hidden"], true ; "line style")]
+    #[test_case(&["--prefix", "> ", "--prefix-style", "blink"], true ; "prefix style")]
+    #[test_case(&["--replace", "${1:on_blue}"], true ; "template style")]
+    #[test_case(&["--highlight-duplicates"], false ; "default duplicate style")]
+    #[test_case(&["--duplicate-style", "on_blue"], true ; "duplicate style")]
+    fn test_safe_styles(extra: &[&str], rejected: bool) {
+        let args = ["recolor", "(a)", "--safe-styles"].iter().chain(extra);
+        let result = run(Cursor::new("a"), Vec::new(), Args::parse_from(args));
+        assert_eq!(result.is_err(), rejected);
+    }
+
+    #[test]
+    fn test_expand_env_styles() -> Result<()> {
+        let env = |name: &str| match name {
+            "ERROR_STYLE" => Some("red,bold".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        let mut styles = [
+            "error=$ERROR_STYLE",
+            "warn=${ERROR_STYLE}",
+            "info=${UNSET:-blue}",
+            "debug=${EMPTY:-dimmed}",
+            "trace=green",
+        ]
+        .map(String::from);
+        expand_env_styles(&mut styles, env)?;
+        assert_eq!(
+            styles,
+            [
+                "error=red,bold",
+                "warn=red,bold",
+                "info=blue",
+                "debug=dimmed",
+                "trace=green"
+            ]
+        );
+        assert!(expand_env_styles(&mut ["error=$UNSET".to_string()], env).is_err());
+        Ok(())
+    }
+
+    #[test_case("" ; "empty")]
+    #[test_case("red,nope" ; "unknown color")]
+    fn test_invalid_default_palette(palette: &str) {
+        let args = Args::parse_from(["recolor", "(a)", "--default-palette", palette]);
+        assert!(run(Cursor::new("a"), Vec::new(), args).is_err());
+    }
+
+    #[test_case("<<>>", &[] ; "no comma")]
+    #[test_case("<<,>>", &["nope=[,]"] ; "unknown group")]
+    #[test_case("<<,>>", &["1"] ; "no group")]
+    fn test_invalid_delimiters(default: &str, group_delimiters: &[&str]) {
+        let groups = vec![("0".to_string(), None), ("1".to_string(), None)];
+        let group_delimiters: Vec<String> =
+            group_delimiters.iter().map(|s| s.to_string()).collect();
+        assert!(Delimiters::new(default, &group_delimiters, &groups).is_err());
+    }
+
+    #[test_case((0, Some(3)), "ab\tc", (0, 2) ; "tab past the end")]
+    #[test_case((1, Some(8)), "a\tb", (1, 3) ; "tab inside")]
+    #[test_case((2, Some(8)), "a\tb", (2, 3) ; "tab starting before the start")]
+    #[test_case((1, None), "\tb", (1, 2) ; "tab before the start")]
+    #[test_case((2, Some(3)), "a\tb", (1, 1) ; "inside a tab")]
+    fn test_column_range_with_tab_stop(columns: Range, line: &str, expected: (usize, usize)) {
+        assert_eq!(column_range(line, columns, Some(4)), expected);
+    }
+
+    #[test]
+    fn test_parse_template() -> Result<()> {
+        let names = [None, Some("name"), None];
+        assert_eq!(
+            parse_template("$$$1 ${name}x $${2}", &names, &[])?,
+            vec![
+                TemplatePart::Literal("$".to_string()),
+                TemplatePart::Group(1),
+                TemplatePart::Literal(" ".to_string()),
+                TemplatePart::Group(1),
+                TemplatePart::Literal("x ${2}".to_string()),
+            ]
+        );
+        assert!(parse_template("$", &names, &[]).is_err());
+        assert!(parse_template("$ 1", &names, &[]).is_err());
+        assert!(parse_template("${name", &names, &[]).is_err());
+        assert!(parse_template("$3", &names, &[]).is_err());
+        assert!(parse_template("$other", &names, &[]).is_err());
+        assert_eq!(
+            parse_template("${name:red}$[bold]{!}", &names, &[])?,
+            vec![
+                TemplatePart::StyledGroup(1, GroupStyle::Fixed(Style::new().red().into())),
+                TemplatePart::StyledLiteral(
+                    "!".to_string(),
+                    GroupStyle::Fixed(Style::new().bold().into())
+                ),
+            ]
+        );
+        assert!(parse_template("${name:nope}", &names, &[]).is_err());
+        assert!(parse_template("$[red]", &names, &[]).is_err());
+        assert!(parse_template("$[red]{x", &names, &[]).is_err());
+        assert!(parse_template("$[match@other(a=red)]{x}", &names, &[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_range() -> Result<()> {
+        assert_eq!(parse_range("3:5")?, (2, Some(5)));
+        assert_eq!(parse_range(":5")?, (0, Some(5)));
+        assert_eq!(parse_range("3:")?, (2, None));
+        assert!(parse_range("0:5").is_err());
+        assert!(parse_range("5:3").is_err());
+        assert!(parse_range("5").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_line_anchors() -> Result<()> {
+        let args = Args::parse_from(["recolor", "^b$"]);
+        assert!(!build_regex(&args)?.is_match("a\nb"));
+
+        let args = Args::parse_from(["recolor", "^b$", "--multi-line-anchors"]);
+        assert!(build_regex(&args)?.is_match("a\nb"));
+        Ok(())
+    }
+
+    #[test_case("rgb(1,2)" ; "too few channels")]
+    #[test_case("rgb(256,0,0)" ; "channel too large")]
+    #[test_case("rgb(101%,0%,0%)" ; "percentage too large")]
+    #[test_case("rgb(50%,0,0)" ; "mixed percentages and numbers")]
+    fn test_invalid_style(style: &str) {
+        assert!(parse_style(style).is_err());
+    }
+
+    #[test_case("42", Some(TextClass::Num))]
+    #[test_case("-0.5", Some(TextClass::Num))]
+    #[test_case("1.", None)]
+    #[test_case("v2", Some(TextClass::Word))]
+    #[test_case(" \t", Some(TextClass::Space))]
+    #[test_case("->", Some(TextClass::Punct))]
+    #[test_case("", None)]
+    fn test_text_class(text: &str, expected: Option<TextClass>) {
+        assert_eq!(TextClass::of(text), expected);
+    }
+
+    #[test_case("heat(0,100,#00f)" ; "missing color")]
+    #[test_case("heat(0,x,#00f,#f00)" ; "not a number")]
+    #[test_case("heat(5,5,#00f,#f00)" ; "empty range")]
+    #[test_case("heat(0,1,#00f,#f00),bg_gradient(#000,#fff)" ; "with gradient")]
+    fn test_invalid_heat(style: &str) {
+        assert!(parse_group_style(style).is_err());
+    }
+
+    #[test]
+    fn test_invalid_classify() {
+        assert!(parse_group_style("classify(num=red,nope=blue)").is_err());
+    }
+
+    #[test]
+    fn test_0x_hex_color() -> Result<()> {
+        assert_eq!(parse_style("0xff0000")?, parse_style("#ff0000")?);
+        assert!(parse_style("0xff00").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_styles_file() -> Result<()> {
+        let contents = "# errors\nerror=red,bold\n\n  # warnings\n  warn=#ffff00\n";
+        assert_eq!(
+            parse_styles_file(contents)?,
+            vec!["error=red,bold", "warn=#ffff00"]
+        );
+        assert!(parse_styles_file("error=red\nwarn\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_rules() -> Result<()> {
+        let contents = "# levels\n\n(?P<level>[A-Z]+) (.*)\nlevel=red\n# messages\n2=dimmed\n";
+        let (regex, styles) = parse_rules(contents)?;
+        assert_eq!(regex, "(?P<level>[A-Z]+) (.*)");
+        assert_eq!(styles, vec!["level=red", "2=dimmed"]);
+        assert!(parse_rules("# just a comment\n\n").is_err());
+        let err = parse_rules("(a)\n1=red\nnope\n").unwrap_err();
+        assert!(err.to_string().contains("line 3"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rules() -> Result<()> {
+        let rules = TempFile::new("test.rules", "(?P<a>a)(?P<b>b)\na=red\nb=red\n")?;
+        let args = Args::parse_from([
+            "recolor".as_ref(),
+            "--rules".as_ref(),
+            rules.path(),
+            "b=blue".as_ref(),
+        ]);
+        assert_eq!(
+            process_str("ab", &args)?,
+            format!(
+                "{}{}\n",
+                "a".style(Style::new().red()),
+                "b".style(Style::new().blue())
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ndjson() -> Result<()> {
+        let args = Args::parse_from([
+            "recolor",
+            "(?P<key>\\w+)=(\\w+)?",
+            "--output",
+            "ndjson",
+            "--no-default-color",
+            "key=red",
+        ]);
+        assert_eq!(
+            process_str("a=1 b=\nnone\n\"é\"\tc=x", &args)?,
+            [
+                r#"{"line":1,"text":"a=1 b=","matches":[{"group":"key","start":0,"end":1,"styled":true},{"group":"2","start":2,"end":3,"styled":false},{"group":"key","start":4,"end":5,"styled":true}]}"#,
+                r#"{"line":2,"text":"none","matches":[]}"#,
+                r#"{"line":3,"text":"\"é\"\tc=x","matches":[{"group":"key","start":5,"end":6,"styled":true},{"group":"2","start":7,"end":8,"styled":false}]}"#,
+                "",
+            ]
+            .join("\n")
+        );
+        assert_eq!(json_string("\u{1}\\"), r#""\u0001\\""#);
+        Ok(())
+    }
+
+    #[test]
+    fn test_style_for_missing_group() {
+        let args = Args::parse_from(["recolor", "(a)(b)", "3=red"]);
+        let err = run(Cursor::new("ab"), Vec::new(), args).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "there is a style for group 3 but the regex only has 2 groups"
+        );
+
+        let args = Args::parse_from(["recolor", "(a)(b)", "2=match@level(x=red)"]);
+        let err = run(Cursor::new("ab"), Vec::new(), args).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "match@level refers to a group the regex doesn't have"
+        );
+    }
+
+    #[test]
+    fn test_ansi_index_colors() -> Result<()> {
+        assert_eq!(parse_style("ansi(1)")?.owo, Style::new().red());
+        assert_eq!(parse_style("a9")?.owo, Style::new().bright_red());
+        assert_eq!(parse_style("on_a15")?.owo, Style::new().on_bright_white());
+        assert!(parse_style("ansi(16)").is_err());
+        assert!(parse_style("a16").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tinted() -> Result<()> {
+        assert_eq!(
+            parse_style("red,tinted,bold")?.owo,
+            Style::new().red().on_truecolor(62, 0, 0).bold()
+        );
+        assert_eq!(
+            parse_style("tinted,#ff8000")?.owo,
+            Style::new().truecolor(255, 128, 0).on_truecolor(77, 38, 0)
+        );
+        assert!(parse_style("on_red,tinted").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes() -> Result<()> {
+        let args = Args::parse_from(["recolor", r"b(?-u:(\xff))", "--bytes"]);
+        let mut output = Vec::new();
+        run(Cursor::new(b"a\xffb\xff\r\n\xfe\n"), &mut output, args)?;
+
+        let mut expected = b"a\xffb".to_vec();
+        expected.extend(format!("{}", Prefix(DEFAULT_STYLES[1])).bytes());
+        expected.push(0xff);
+        expected.extend(b"\x1b[0m\n\xfe\n");
+        assert_eq!(output, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_with_text_options() -> Result<()> {
+        let args = Args::parse_from([
+            "recolor",
+            r"(?-u:\xff)(c)",
+            "--bytes",
+            "--only-matching",
+            "--no-default-color",
+        ]);
+        let mut output = Vec::new();
+        assert_eq!(run(Cursor::new(b"a\xffb\xffc\n"), &mut output, args)?, 1);
+        assert_eq!(output, b"\xffc\n");
+
+        let args = Args::parse_from([
+            "recolor",
+            "(b)",
+            "--bytes",
+            "--max-line-length",
+            "2",
+            "--no-default-color",
+        ]);
+        let mut output = Vec::new();
+        run(Cursor::new(b"\xffbc\n"), &mut output, args)?;
+        assert_eq!(output, [b"\xffb", "…\n".as_bytes()].concat());
+        Ok(())
+    }
+
+    #[test]
+    fn test_keep_going() -> Result<()> {
+        let input: &[u8] = b"ab\n\xff\nb\n";
+        let args = Args::parse_from(["recolor", "(b)", "--no-default-color"]);
+        assert!(run(input, Vec::new(), args).is_err());
+
+        let args = Args::parse_from(["recolor", "(b)", "--no-default-color", "--keep-going"]);
+        let mut output = Vec::new();
+        assert_eq!(run(input, &mut output, args)?, 2);
+        assert_eq!(output, b"ab\nb\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_styles_files() -> Result<()> {
+        let base = TempFile::new("base.styles", "a=red\nb=red\nc=red\n")?;
+        let project = TempFile::new("project.styles", "b=green\nc=green\n")?;
+        let args = Args::parse_from([
+            "recolor".as_ref(),
+            "(?P<a>a)(?P<b>b)(?P<c>c)".as_ref(),
+            "c=blue".as_ref(),
+            "--styles-file".as_ref(),
+            base.path(),
+            "--styles-file".as_ref(),
+            project.path(),
+        ]);
+        assert_eq!(
+            process_str("abc", &args)?,
+            format!(
+                "{}{}{}\n",
+                "a".style(Style::new().red()),
+                "b".style(Style::new().green()),
+                "c".style(Style::new().blue())
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_styles_stdin() -> Result<()> {
+        assert!(Args::try_parse_from(["recolor", "(a)", "--styles-stdin"]).is_err());
+
+        let mut args = Args::parse_from([
+            "recolor",
+            "(?P<a>a)(?P<b>b)",
+            "a=red",
+            "--styles-stdin",
+            "--input",
+            "input.log",
+        ]);
+        read_styles(Cursor::new("  b=blue \n\n"), &mut args.styles)?;
+        assert_eq!(args.styles, ["a=red", "b=blue"]);
+        assert_eq!(
+            process_str("ab", &args)?,
+            format!(
+                "{}{}\n",
+                "a".style(Style::new().red()),
+                "b".style(Style::new().blue())
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_config() -> Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            styles = ["name=red", "1=bold"]
+            palette = ["blue"]
+            "#,
+        )?;
+        let mut args = Args::parse_from(["recolor", "(a)(?P<name>b)(c)", "1=underline"]);
+        args.config = config;
+        let mut output = Vec::new();
+        run(Cursor::new("abc"), &mut output, args)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            format!(
+                "{}{}{}\n",
+                "a".style(Style::new().underline()),
+                "b".style(Style::new().red()),
+                "c".style(Style::new().blue())
+            )
+        );
+
+        assert!(toml::from_str::<Config>("colours = []").is_err());
+        assert!(load_config(Path::new("/does/not/exist.toml"))?
+            .styles
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_svg_output() -> Result<()> {
+        let args = Args::parse_from([
+            "recolor",
+            "(b)(c)",
+            "1=bold,#ff0000",
+            "2=white,on_blue,underline",
+            "--output",
+            "svg",
+        ]);
+        let svg = process_str("a<bc\n", &args)?;
+        assert!(
+            svg.starts_with(r##"<svg xmlns="http://www.w3.org/2000/svg" width="56" height="40">"##)
+        );
+        assert!(svg.contains(r##"<tspan x="10" y="25">a&lt;</tspan>"##));
+        assert!(
+            svg.contains(r##"<tspan x="28" y="25" fill="#ff0000" font-weight="bold">b</tspan>"##)
+        );
+        assert!(svg.contains(r##"<rect x="37" y="10" width="9" height="20" fill="#0000ee"/>"##));
+        assert!(svg.contains(
+            r##"<tspan x="37" y="25" fill="#e5e5e5" text-decoration="underline">c</tspan>"##
+        ));
+
+        // Other escape sequences, like hyperlinks, aren't part of the text.
+        let svg = svg::render(b"see \x1b]8;;https://x.com\x1b\\\x1b[34mx.com\x1b[0m\x1b]8;;\x07\n");
+        assert!(svg.contains(r##"<tspan x="10" y="25">see </tspan>"##));
+        assert!(svg.contains(r##"<tspan x="46" y="25" fill="#0000ee">x.com</tspan>"##));
+        assert!(!svg.contains("8;;"));
+
+        let args = Args::parse_from(["recolor", "--urls", "--output", "svg"]);
+        let svg = process_str("see https://x.com\n", &args)?;
+        assert!(
+            svg.contains(r##"fill="#0000ee" text-decoration="underline">https://x.com</tspan>"##)
+        );
+        let mut output = Vec::new();
+        run(Cursor::new("see https://x.com\n"), &mut output, args)?;
+        assert!(!String::from_utf8(output)?.contains("\x1b]8"));
+        Ok(())
+    }
+
+    #[cfg(feature = "tui")]
+    #[test]
+    fn test_tui_preview() {
+        use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        let args = Args::parse_from(["recolor", "(a)"]);
+        let mut preview = tui::Preview::new(args, "abc\nbcd".to_string());
+        for code in [
+            KeyCode::Backspace,
+            KeyCode::Backspace,
+            KeyCode::Char('b'),
+            KeyCode::Char(')'),
+            KeyCode::Tab,
+        ]
+        .into_iter()
+        .chain("1=red".chars().map(KeyCode::Char))
+        {
+            assert_eq!(preview.handle(key(code)), tui::Action::Continue);
+        }
+        assert_eq!(
+            preview.render(12, 5),
+            vec![
+                "  regex:  (b",
+                "> styles: 1=",
+                "────────────",
+                "a\x1b[31mb\x1b[0mc",
+                "\x1b[31mb\x1b[0mcd",
+            ]
+        );
+        assert_eq!(preview.command(), "recolor '(b)' '1=red'");
+        assert_eq!(preview.handle(key(KeyCode::Enter)), tui::Action::Accept);
+
+        preview.handle(key(KeyCode::Char('2')));
+        assert_eq!(preview.render(80, 4)[3], "invalid style: \"red2\"");
+        assert_eq!(preview.handle(key(KeyCode::Esc)), tui::Action::Cancel);
+    }
+
+    #[cfg(feature = "image-palette")]
+    #[test]
+    fn test_median_cut() {
+        let pixels = [[0, 0, 0]; 6].into_iter().chain([[255, 255, 255]; 2]);
+        let cut = |n| image_palette::median_cut(pixels.clone().collect(), n);
+        assert_eq!(cut(1), vec![(63, 63, 63)]);
+        assert_eq!(cut(3), vec![(0, 0, 0), (255, 255, 255)]);
+        assert_eq!(cut(8), vec![(0, 0, 0), (255, 255, 255)]);
+        assert_eq!(image_palette::median_cut(Vec::new(), 8), vec![]);
+    }
+
+    #[test]
+    fn test_color_by_name() -> Result<()> {
+        let style = |regex: &str, input: &str| -> Result<String> {
+            let args = Args::parse_from(["recolor", regex, "--color-by-name"]);
+            process_str(input, &args)
+        };
+        let error = style("(?P<error>e)", "e")?;
+        let warn = style("(?P<warn>w)", "w")?;
+        assert_ne!(error.replace('e', ""), warn.replace('w', ""));
+        assert_eq!(style("(?P<error>e)|(?P<warn>w)", "e")?, error);
+        assert_eq!(style("(?P<warn>w)|(?P<error>e)", "e")?, error);
+        assert_eq!(style("(?P<warn>w)|(?P<error>e)", "w")?, warn);
+        Ok(())
+    }
+
+    #[test]
+    fn test_escapes() -> Result<()> {
+        let args = Args::parse_from(["recolor", "--print-escape", "error", "error=red,bold"]);
+        assert_eq!(escapes(args, "error")?, b"\x1b[31;1m\n\x1b[0m\n");
+
+        let args = Args::parse_from([
+            "recolor",
+            "--print-escape",
+            "error",
+            "--escaped",
+            "--colors",
+            "16",
+            "error=#ff0000",
+        ]);
+        assert_eq!(escapes(args, "error")?, b"\\e[91m\n\\e[0m\n");
+
+        let args = Args::parse_from(["recolor", "--print-escape", "warn", "error=red"]);
+        assert!(escapes(args, "warn").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_color_test() -> Result<()> {
+        let mut output = Vec::new();
+        let var = |name: &str| (name == "TERM").then(|| "xterm-256color".to_string());
+        write_color_test(&mut output, false, var, ansi::ColorDepth::TrueColor)?;
+        let output = String::from_utf8(output)?;
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[..6],
+            [
+                "stdout is a terminal: no",
+                "COLORTERM: (not set)",
+                "TERM: xterm-256color",
+                "NO_COLOR: (not set)",
+                "detected colors: 256",
+                "using colors: truecolor",
+            ]
+        );
+        assert!(lines[6].starts_with("       16: \x1b[101m \x1b[0m"));
+        assert!(lines[7].starts_with("      256: \x1b[48;5;196m \x1b[0m"));
+        assert!(lines[8].starts_with("truecolor: \x1b[48;2;255;0;0m \x1b[0m"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_debug_match() {
+        assert_eq!(
+            debug_match(3, "name", &(4..6), b"a\"b"),
+            r#"line 3: group name matched 4..6: "a\"b""#
+        );
+        assert_eq!(
+            debug_match(1, "2", &(0..2), b"\xffc"),
+            "line 1: group 2 matched 0..2: \"\u{fffd}c\""
+        );
+    }
+
+    #[test]
+    fn test_coverage() {
+        let groups = vec![("0".to_string(), None), ("name".to_string(), None)];
+        let mut coverage = Coverage::new(false, true, groups.len());
+        coverage.add_line("aéb");
+        coverage.add_line("é");
+        coverage.add_match("éb");
+        coverage.add_group(1, "b");
+        assert_eq!(
+            coverage.report(&groups),
+            "matched 2 of 4 chars (50.0%)\n  name: 1 chars\n"
+        );
+
+        let mut coverage = Coverage::new(true, false, groups.len());
+        coverage.add_line("aéb");
+        coverage.add_match("éb");
+        assert_eq!(
+            coverage.report(&groups),
+            "matched 3 of 4 bytes (75.0%)\n  name: 0 bytes\n"
+        );
+    }
+
+    #[test]
+    fn test_progress() -> Result<()> {
+        let mut progress = Progress::new(true);
+        let start = progress.start;
+        let at = |seconds| start + Duration::from_secs_f64(seconds);
+        let mut output = Vec::new();
+        for _ in 0..1023 {
+            progress.tick_with(|| at(2.0), &mut output)?;
+        }
+        assert!(output.is_empty());
+        progress.tick_with(|| at(2.0), &mut output)?;
+        assert_eq!(
+            String::from_utf8(output.clone())?,
+            "\r1024 lines, 512 lines/s"
+        );
+
+        // Less than a second after the last report, there isn't another.
+        output.clear();
+        for _ in 0..1024 {
+            progress.tick_with(|| at(2.5), &mut output)?;
+        }
+        assert!(output.is_empty());
+        progress.finish_with(at(4.0), &mut output)?;
+        assert_eq!(String::from_utf8(output)?, "\r2048 lines, 512 lines/s\n");
+
+        let mut progress = Progress::new(false);
+        let mut output = Vec::new();
+        for _ in 0..2048 {
+            progress.tick_with(|| at(2.0), &mut output)?;
+        }
+        progress.finish_with(at(2.0), &mut output)?;
+        assert!(output.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats() -> Result<()> {
+        let red = GroupStyle::Fixed(Style::new().red().into());
+        let groups = vec![
+            ("0".to_string(), None),
+            ("word".to_string(), Some(&red)),
+            ("none".to_string(), None),
+        ];
+        let mut stats = Stats::new(true, groups.len());
+        for text in ["ab", "abc", "abcdéfgh", "abcdéfgh", "abcdéfgh"] {
+            stats.add_group(1, text);
+        }
+        let render = Render {
+            no_color: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            String::from_utf8(stats.report(&groups, &[], render)?)?,
+            [
+                "word: 5 matches",
+                "        2-3 ████████████████████ 2",
+                "        4-7  0",
+                "       8-15 ██████████████████████████████ 3",
+                "none: 0 matches",
+                "",
+            ]
+            .join("\n")
+        );
+
+        let render = Render::default();
+        let report = String::from_utf8(stats.report(&groups, &[], render)?)?;
+        assert!(report.contains(&format!("{} 2", "█".repeat(20).style(Style::new().red()))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_match_count() -> Result<()> {
+        let args = Args::parse_from(["recolor", "(a)", "--columns", ":3"]);
+        assert_eq!(run(Cursor::new("a\nb\nab a"), Vec::new(), args)?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_all() -> Result<()> {
+        let args = Args::parse_from(["recolor", "(a)", "--with-filename", "--color", "never"]);
+        let inputs = [("one", "a\nb\n"), ("two", "aa\n")].map(|(name, text)| {
+            Ok(Input {
+                name: Some(name.to_string()),
+                reader: Box::new(text.as_bytes()),
+            })
+        });
+        let mut output = Vec::new();
+        assert_eq!(run_all(inputs.into_iter(), &mut output, args)?, 3);
+        assert_eq!(String::from_utf8(output)?, "one:a\none:b\ntwo:aa\n");
+
+        // The legend and reset come once, before the first input.
+        let args = Args::parse_from([
+            "recolor",
+            "(a)",
+            "--legend",
+            "--reset-on-start",
+            "--no-default-color",
+        ]);
+        let inputs = ["a\n", "b\n"].map(|text| {
+            Ok(Input {
+                name: None,
+                reader: Box::new(text.as_bytes()),
+            })
+        });
+        let mut output = Vec::new();
+        run_all(inputs.into_iter(), &mut output, args)?;
+        assert_eq!(String::from_utf8(output)?, "\x1b[0m1\na\nb\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_trailing_newline_inputs() -> Result<()> {
+        let one = TempFile::new("one.log", "a1\n")?;
+        let two = TempFile::new("two.log", "b2\nc3\n")?;
+        for mode in ["--no-default-color", "--bytes"] {
+            let args = Args::parse_from([
+                "recolor".as_ref(),
+                "(x)".as_ref(),
+                mode.as_ref(),
+                "--no-trailing-newline".as_ref(),
+                "--input".as_ref(),
+                one.path(),
+                "--input".as_ref(),
+                two.path(),
+            ]);
+            let mut output = Vec::new();
+            run_inputs(&args, &mut output)?;
+            assert_eq!(String::from_utf8(output)?, "a1\nb2\nc3");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_buffer_size() -> Result<()> {
+        let input = TempFile::new("buffer.log", "one ab\ntwo ab three\n")?;
+        let args = Args::parse_from([
+            "recolor".as_ref(),
+            "(a)b".as_ref(),
+            "--input".as_ref(),
+            input.path(),
+            "--buffer-size".as_ref(),
+            "3".as_ref(),
+        ]);
+        let mut output = Vec::new();
+        let matches = run_inputs(&args, BufWriter::with_capacity(3, &mut output))?;
+        assert_eq!(matches, 2);
+        let a = "a".style(Style::new().green());
+        assert_eq!(
+            String::from_utf8(output)?,
+            format!("one {}b\ntwo {}b three\n", a, a)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+//! Rendering of recolor's ANSI-styled output as an SVG image, for putting
+//! colored output in documentation.
+
+use crate::ansi::{split_escapes, xterm_color, Rgb, XTERM_COLORS};
+use std::fmt::Write;
+
+/// Size of each character cell, in pixels. Monospace fonts are about 0.6em
+/// wide, so this lines up at a font size of 15px.
+const CELL_WIDTH: usize = 9;
+const CELL_HEIGHT: usize = 20;
+const FONT_SIZE: usize = 15;
+const PADDING: usize = 10;
+const TAB_WIDTH: usize = 8;
+
+const BACKGROUND: Rgb = (0x1e, 0x1e, 0x1e);
+const FOREGROUND: Rgb = (0xd4, 0xd4, 0xd4);
+
+/// The styles in effect at some point in the output.
+#[derive(Clone, Copy, Default, PartialEq)]
+struct Pen {
+    fg: Option<Rgb>,
+    bg: Option<Rgb>,
+    bold: bool,
+    dimmed: bool,
+    italic: bool,
+    underline: bool,
+    strikethrough: bool,
+    reversed: bool,
+    hidden: bool,
+}
+
+impl Pen {
+    /// Applies the parameters of an SGR sequence, `ESC [ ... m`.
+    fn apply(&mut self, params: &str) {
+        let params: Vec<u8> = params.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+        let mut params = params.into_iter();
+        while let Some(param) = params.next() {
+            match param {
+                0 => *self = Pen::default(),
+                1 => self.bold = true,
+                2 => self.dimmed = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                7 => self.reversed = true,
+                8 => self.hidden = true,
+                9 => self.strikethrough = true,
+                22 => (self.bold, self.dimmed) = (false, false),
+                23 => self.italic = false,
+                24 => self.underline = false,
+                27 => self.reversed = false,
+                28 => self.hidden = false,
+                29 => self.strikethrough = false,
+                30..=37 => self.fg = Some(XTERM_COLORS[(param - 30) as usize]),
+                90..=97 => self.fg = Some(XTERM_COLORS[(param - 90 + 8) as usize]),
+                40..=47 => self.bg = Some(XTERM_COLORS[(param - 40) as usize]),
+                100..=107 => self.bg = Some(XTERM_COLORS[(param - 100 + 8) as usize]),
+                38 | 48 => {
+                    let color = match params.next() {
+                        Some(5) => params.next().map(xterm_color),
+                        Some(2) => match (params.next(), params.next(), params.next()) {
+                            (Some(r), Some(g), Some(b)) => Some((r, g, b)),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    if param == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                }
+                39 => self.fg = None,
+                49 => self.bg = None,
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns the foreground and background colors to draw with.
+    fn colors(&self) -> (Rgb, Option<Rgb>) {
+        let fg = self.fg.unwrap_or(FOREGROUND);
+        if self.reversed {
+            (self.bg.unwrap_or(BACKGROUND), Some(fg))
+        } else {
+            (fg, self.bg)
+        }
+    }
+}
+
+/// A run of text in the same style, starting at `column`.
+struct Run {
+    column: usize,
+    pen: Pen,
+    text: String,
+}
+
+/// Splits `ansi` into lines of styled runs.
+fn parse(ansi: &str) -> Vec<Vec<Run>> {
+    let mut lines: Vec<Vec<Run>> = vec![Vec::new()];
+    let mut pen = Pen::default();
+    let mut column = 0;
+    let (text, escapes) = split_escapes(ansi);
+    let mut escapes = escapes.into_iter().peekable();
+    for (i, c) in text.char_indices() {
+        while let Some((_, sequences)) = escapes.next_if(|(position, _)| *position == i) {
+            // Only SGR sequences change how text looks. The rest, like
+            // hyperlinks, are left out of the image.
+            for sequence in sequences.split('\x1b') {
+                if let Some(params) = sequence.strip_prefix('[').and_then(|s| s.strip_suffix('m')) {
+                    pen.apply(params);
+                }
+            }
+        }
+        match c {
+            '\n' => {
+                lines.push(Vec::new());
+                column = 0;
+            }
+            c => {
+                let text = if c == '\t' {
+                    " ".repeat(TAB_WIDTH - column % TAB_WIDTH)
+                } else {
+                    c.to_string()
+                };
+                let line = lines.last_mut().unwrap();
+                match line.last_mut() {
+                    Some(run) if run.pen == pen => run.text.push_str(&text),
+                    _ => line.push(Run {
+                        column,
+                        pen,
+                        text: text.clone(),
+                    }),
+                }
+                column += text.chars().count();
+            }
+        }
+    }
+    if lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+fn hex((r, g, b): Rgb) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Renders ANSI-styled text as an SVG image, with characters laid out on a
+/// monospace grid.
+pub fn render(ansi: &[u8]) -> String {
+    let lines = parse(&String::from_utf8_lossy(ansi));
+    let columns = lines
+        .iter()
+        .filter_map(|line| line.last())
+        .map(|run| run.column + run.text.chars().count())
+        .max()
+        .unwrap_or(0);
+    let width = columns * CELL_WIDTH + PADDING * 2;
+    let height = lines.len() * CELL_HEIGHT + PADDING * 2;
+
+    let mut backgrounds = String::new();
+    let mut text = String::new();
+    for (row, line) in lines.iter().enumerate() {
+        let top = PADDING + row * CELL_HEIGHT;
+        // Centre the text vertically in its cell.
+        let baseline = top + CELL_HEIGHT - (CELL_HEIGHT - FONT_SIZE) / 2 - 3;
+        for run in line {
+            let x = PADDING + run.column * CELL_WIDTH;
+            let (fg, bg) = run.pen.colors();
+            if let Some(bg) = bg {
+                let _ = writeln!(
+                    backgrounds,
+                    r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+                    x,
+                    top,
+                    run.text.chars().count() * CELL_WIDTH,
+                    CELL_HEIGHT,
+                    hex(bg)
+                );
+            }
+            if run.pen.hidden || run.text.trim().is_empty() {
+                continue;
+            }
+
+            let mut attributes = format!(r#"x="{}" y="{}""#, x, baseline);
+            if fg != FOREGROUND {
+                let _ = write!(attributes, r#" fill="{}""#, hex(fg));
+            }
+            if run.pen.bold {
+                attributes.push_str(r#" font-weight="bold""#);
+            }
+            if run.pen.italic {
+                attributes.push_str(r#" font-style="italic""#);
+            }
+            if run.pen.dimmed {
+                attributes.push_str(r#" opacity="0.5""#);
+            }
+            let decorations: Vec<&str> = [
+                (run.pen.underline, "underline"),
+                (run.pen.strikethrough, "line-through"),
+            ]
+            .into_iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, decoration)| decoration)
+            .collect();
+            if !decorations.is_empty() {
+                let _ = write!(
+                    attributes,
+                    r#" text-decoration="{}""#,
+                    decorations.join(" ")
+                );
+            }
+            let _ = writeln!(text, "<tspan {}>{}</tspan>", attributes, escape(&run.text));
+        }
+    }
+
+    format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}">"#,
+            "\n",
+            r#"<rect width="100%" height="100%" fill="{background}"/>"#,
+            "\n{backgrounds}",
+            r#"<text xml:space="preserve" font-family="monospace" font-size="{font_size}" fill="{foreground}">"#,
+            "\n{text}</text>\n</svg>\n"
+        ),
+        width = width,
+        height = height,
+        background = hex(BACKGROUND),
+        backgrounds = backgrounds,
+        font_size = FONT_SIZE,
+        foreground = hex(FOREGROUND),
+        text = text,
+    )
+}
@@ -0,0 +1,197 @@
+//! The interactive preview for `--tui`: a sample of the input, colored with
+//! the regex and styles as they're typed, for working out what to give
+//! recolor without running it over and over.
+
+use crate::{ansi, process_str, Args};
+use anyhow::{Context, Result};
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::Print,
+    terminal::{self, ClearType},
+};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+};
+
+/// How many lines of the input are kept to try the styles out on.
+const SAMPLE_LINES: usize = 1000;
+
+/// What to do after a key press.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Continue,
+    /// Stop, and print the command line for what was typed.
+    Accept,
+    /// Stop without printing anything.
+    Cancel,
+}
+
+/// The regex and styles being typed, and the sample they're tried out on.
+pub struct Preview {
+    args: Args,
+    sample: String,
+    regex: String,
+    /// The styles, separated by spaces like on the command line.
+    styles: String,
+    /// Whether keys go to the styles instead of the regex.
+    editing_styles: bool,
+}
+
+impl Preview {
+    pub fn new(args: Args, sample: String) -> Self {
+        Preview {
+            regex: args.regex.clone().unwrap_or_default(),
+            styles: args.styles.join(" "),
+            args,
+            sample,
+            editing_styles: false,
+        }
+    }
+
+    pub fn handle(&mut self, key: KeyEvent) -> Action {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        let field = if self.editing_styles {
+            &mut self.styles
+        } else {
+            &mut self.regex
+        };
+        match key.code {
+            KeyCode::Char('c') if control => return Action::Cancel,
+            KeyCode::Char('u') if control => field.clear(),
+            KeyCode::Char(c) => field.push(c),
+            KeyCode::Backspace => {
+                field.pop();
+            }
+            KeyCode::Tab | KeyCode::BackTab | KeyCode::Up | KeyCode::Down => {
+                self.editing_styles = !self.editing_styles
+            }
+            KeyCode::Enter => return Action::Accept,
+            KeyCode::Esc => return Action::Cancel,
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    /// The args with the regex and styles typed so far in place of the ones
+    /// recolor was run with.
+    fn args(&self) -> Args {
+        Args {
+            regex: Some(self.regex.clone()).filter(|regex| !regex.is_empty()),
+            styles: self.styles.split_whitespace().map(str::to_string).collect(),
+            ..self.args.clone()
+        }
+    }
+
+    /// Returns what to show on a terminal `width` columns wide and `height`
+    /// rows tall: the regex and styles, then the sample colored with them, or
+    /// why they can't be used. Until there's a regex, the sample is shown as
+    /// it is.
+    pub fn render(&self, width: usize, height: usize) -> Vec<String> {
+        let marker = |editing| if editing { '>' } else { ' ' };
+        let mut lines = vec![
+            format!("{} regex:  {}", marker(!self.editing_styles), self.regex),
+            format!("{} styles: {}", marker(self.editing_styles), self.styles),
+            "─".repeat(width),
+        ];
+        let args = self.args();
+        let colored = match args.regex {
+            Some(_) => process_str(&self.sample, &args),
+            None => Ok(self.sample.clone()),
+        };
+        match colored {
+            Ok(colored) => lines.extend(colored.lines().map(str::to_string)),
+            Err(e) => lines.push(format!("{:#}", e)),
+        }
+        lines.truncate(height);
+        lines
+            .into_iter()
+            .map(|line| ansi::truncate(&line, width).unwrap_or(line))
+            .collect()
+    }
+
+    /// Where the cursor goes, at the end of the text being typed.
+    fn cursor(&self) -> (usize, usize) {
+        let (field, row) = if self.editing_styles {
+            (&self.styles, 1)
+        } else {
+            (&self.regex, 0)
+        };
+        (field.chars().count() + "> styles: ".len(), row)
+    }
+
+    /// The command line that colors the input like the preview does.
+    pub fn command(&self) -> String {
+        let args = self.args();
+        let quote = |arg: String| format!("'{}'", arg.replace('\'', r"'\''"));
+        std::iter::once("recolor".to_string())
+            .chain(args.regex.into_iter().chain(args.styles).map(quote))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Reads the first lines of the input files, or stdin if there aren't any.
+fn read_sample(args: &Args) -> Result<String> {
+    let readers: Vec<Box<dyn BufRead>> = if args.input.is_empty() {
+        vec![Box::new(io::stdin().lock())]
+    } else {
+        args.input
+            .iter()
+            .map(|path| {
+                let file =
+                    File::open(path).context(format!("failed to open {}", path.display()))?;
+                Ok(Box::new(BufReader::new(file)) as Box<dyn BufRead>)
+            })
+            .collect::<Result<_>>()?
+    };
+    let lines = readers
+        .into_iter()
+        .flat_map(BufRead::lines)
+        .take(SAMPLE_LINES)
+        .collect::<io::Result<Vec<_>>>()
+        .context("failed to read the input")?;
+    Ok(lines.join("\n"))
+}
+
+/// Shows the preview until it's accepted or cancelled, then prints the
+/// command line for it if it was accepted. Keys are read from the terminal,
+/// so the input can still be piped in.
+pub fn run(args: Args) -> Result<()> {
+    let mut preview = Preview::new(args.clone(), read_sample(&args)?);
+    let mut output = io::stdout();
+    terminal::enable_raw_mode()?;
+    execute!(output, terminal::EnterAlternateScreen)?;
+    let action = edit(&mut preview, &mut output);
+    execute!(output, terminal::LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+    if action? == Action::Accept {
+        writeln!(output, "{}", preview.command())?;
+    }
+    Ok(())
+}
+
+fn edit(preview: &mut Preview, output: &mut impl Write) -> Result<Action> {
+    loop {
+        let (width, height) = terminal::size()?;
+        queue!(output, terminal::Clear(ClearType::All))?;
+        let lines = preview.render(width.into(), height.into());
+        for (row, line) in lines.iter().enumerate() {
+            queue!(output, cursor::MoveTo(0, row as u16), Print(line))?;
+        }
+        let (column, row) = preview.cursor();
+        queue!(output, cursor::MoveTo(column as u16, row as u16))?;
+        output.flush()?;
+
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press {
+                match preview.handle(key) {
+                    Action::Continue => {}
+                    action => return Ok(action),
+                }
+            }
+        }
+    }
+}